@@ -1,19 +1,17 @@
-use std::{fmt::Display, io::Cursor};
+use std::{fmt::Display, io::Read};
 
 use rubato::{
-    Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
+    ResampleError, Resampler, ResamplerConstructionError, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, WindowFunction,
 };
 use symphonia::core::{
-    audio::AudioBuffer,
-    codecs::{DecoderOptions, CODEC_TYPE_NULL},
-    formats::FormatOptions,
-    io::MediaSourceStream,
+    audio::{AudioBuffer, Signal},
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    formats::{FormatOptions, FormatReader},
+    io::{MediaSourceStream, ReadOnlySource},
     meta::MetadataOptions,
     probe::Hint,
 };
-use yt_tsu::audio::extract_ts_audio;
-
-pub const YOUTUBE_TS_SAMPLE_RATE: u16 = 22050;
 
 #[derive(Debug)]
 pub enum Error {
@@ -21,43 +19,138 @@ pub enum Error {
     Decoder,
     Track,
     Empty,
+    Resample(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use Error::*;
 
-        write!(
-            f,
-            "{}",
-            match self {
-                Format => "unsupported format",
-                Decoder => "no supported audio tracks",
-                Track => "unsupported codec",
-                Empty => "empty audio data",
-            }
-        )
+        match self {
+            Format => write!(f, "unsupported format"),
+            Decoder => write!(f, "no supported audio tracks"),
+            Track => write!(f, "unsupported codec"),
+            Empty => write!(f, "empty audio data"),
+            Resample(e) => write!(f, "failed to resample: {}", e),
+        }
     }
 }
 
-pub fn get_audio_data(data: &[u8]) -> Result<(Vec<f32>, f64), Error> {
-    let ts_audio = extract_ts_audio(data);
+impl std::error::Error for Error {}
+
+impl From<ResamplerConstructionError> for Error {
+    fn from(e: ResamplerConstructionError) -> Self {
+        Error::Resample(e.to_string())
+    }
+}
 
-    if ts_audio.is_empty() {
-        Err(Error::Empty)
-    } else {
-        get_mono_f32(ts_audio)
+impl From<ResampleError> for Error {
+    fn from(e: ResampleError) -> Self {
+        Error::Resample(e.to_string())
     }
 }
 
-fn get_mono_f32(raw: Vec<u8>) -> Result<(Vec<f32>, f64), Error> {
-    let src = Cursor::new(raw);
-    // Create the media source stream.
+/// decode a non mpeg-ts stream with a single decoder,
+/// `f` receives mono pcm with its sample rate and returns false to stop decoding
+pub fn decode_stream<R, F>(reader: R, extension: Option<&str>, mut f: F) -> Result<(), Error>
+where
+    R: Read + Send + Sync + 'static,
+    F: FnMut(&[f32], u32) -> bool,
+{
+    let src = ReadOnlySource::new(reader);
     let mss = MediaSourceStream::new(Box::new(src), Default::default());
-    // Create a probe hint using the file's extension. [Optional]
     let mut hint = Hint::new();
-    hint.with_extension("aac");
+    if let Some(ext) = extension {
+        hint.with_extension(ext);
+    }
+
+    let (mut format, mut decoder, track_id) = probe(mss, &hint)?;
+
+    let mut batcher = PcmBatcher::default();
+    let mut running = true;
+
+    decode_packets(format.as_mut(), decoder.as_mut(), track_id, |buf, _| {
+        running = batcher.push(buf, &mut f);
+        running
+    });
+
+    if running {
+        batcher.flush(&mut f);
+    }
+
+    Ok(())
+}
+
+/// groups decoded buffers into about 1s of mono pcm with the same sample rate
+#[derive(Default)]
+pub struct PcmBatcher {
+    pcm: Vec<f32>,
+    rate: u32,
+}
+
+impl PcmBatcher {
+    /// downmix `buf`, `f` receives every finished batch with its sample rate
+    /// and returns false to stop
+    pub fn push<F>(&mut self, buf: &AudioBuffer<f32>, f: &mut F) -> bool
+    where
+        F: FnMut(&[f32], u32) -> bool,
+    {
+        let rate = buf.spec().rate;
+        let mut running = true;
+
+        // audio of the previous rate goes out first
+        if rate != self.rate {
+            running = self.flush(f);
+            self.rate = rate;
+        }
 
+        downmix_into(buf, &mut self.pcm);
+
+        if running && self.pcm.len() >= rate as usize {
+            running = self.flush(f);
+        }
+
+        running
+    }
+
+    /// hand out the collected pcm
+    pub fn flush<F>(&mut self, f: &mut F) -> bool
+    where
+        F: FnMut(&[f32], u32) -> bool,
+    {
+        if self.pcm.is_empty() {
+            return true;
+        }
+
+        let running = f(&self.pcm, self.rate);
+        self.pcm.clear();
+        running
+    }
+}
+
+/// append the average of all channels of `buf` to `out`
+pub fn downmix_into(buf: &AudioBuffer<f32>, out: &mut Vec<f32>) {
+    let channels = buf.spec().channels.count();
+    if channels <= 1 {
+        out.extend_from_slice(buf.chan(0));
+        return;
+    }
+
+    let start = out.len();
+    out.extend_from_slice(buf.chan(0));
+    for ch in 1..channels {
+        for (sample, other) in out[start..].iter_mut().zip(buf.chan(ch)) {
+            *sample += other;
+        }
+    }
+
+    let scale = 1.0 / channels as f32;
+    out[start..].iter_mut().for_each(|sample| *sample *= scale);
+}
+
+type Probed = (Box<dyn FormatReader>, Box<dyn Decoder>, u32);
+
+fn probe(mss: MediaSourceStream, hint: &Hint) -> Result<Probed, Error> {
     // Use the default options for metadata and format readers.
     let meta_opts: MetadataOptions = Default::default();
     let fmt_opts: FormatOptions = Default::default();
@@ -65,11 +158,11 @@ fn get_mono_f32(raw: Vec<u8>) -> Result<(Vec<f32>, f64), Error> {
 
     // Probe the media source.
     let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &fmt_opts, &meta_opts)
+        .format(hint, mss, &fmt_opts, &meta_opts)
         .map_err(|_| Error::Format)?;
 
     // Get the instantiated format reader.
-    let mut format = probed.format;
+    let format = probed.format;
 
     // Find the first audio track with a known (decodeable) codec.
     let track = format
@@ -79,18 +172,25 @@ fn get_mono_f32(raw: Vec<u8>) -> Result<(Vec<f32>, f64), Error> {
         .ok_or(Error::Track)?;
 
     // Create a decoder for the track.
-    let mut decoder = symphonia::default::get_codecs()
+    let decoder = symphonia::default::get_codecs()
         .make(&track.codec_params, &dec_opts)
         .map_err(|_| Error::Decoder)?;
 
     // Store the track identifier, it will be used to filter packets.
     let track_id = track.id;
 
-    let mut data: Vec<f32> = vec![];
-    let mut dur = 0.0f64;
-    let mut rate = 0.0f64;
-    let mut planes_num = 1.0f64;
+    Ok((format, decoder, track_id))
+}
 
+/// `f` receives every decoded buffer with its packet duration and returns false to stop
+fn decode_packets<F>(
+    format: &mut dyn FormatReader,
+    decoder: &mut dyn Decoder,
+    track_id: u32,
+    mut f: F,
+) where
+    F: FnMut(&AudioBuffer<f32>, u64) -> bool,
+{
     // The decode loop.
     loop {
         // Get the next packet from the media format.
@@ -121,17 +221,8 @@ fn get_mono_f32(raw: Vec<u8>) -> Result<(Vec<f32>, f64), Error> {
                 let mut buf = AudioBuffer::<f32>::new(packet.dur, *audio_buf.spec());
                 audio_buf.convert(&mut buf);
 
-                let planes = buf.planes();
-                let planes = planes.planes();
-                data.extend_from_slice(planes[0]);
-                dur += packet.dur as f64;
-
-                if rate == 0.0 {
-                    rate = audio_buf.spec().rate as f64;
-                }
-
-                if planes_num == 1.0 {
-                    planes_num = planes.len() as f64;
+                if !f(&buf, packet.dur) {
+                    break;
                 }
             }
             Err(symphonia::core::errors::Error::DecodeError(_)) => (),
@@ -140,23 +231,153 @@ fn get_mono_f32(raw: Vec<u8>) -> Result<(Vec<f32>, f64), Error> {
             }
         }
     }
+}
+
+/// input frames of each resampler call
+const RESAMPLE_FRAMES: usize = 1024;
 
-    Ok((data, dur / (rate * planes_num)))
+/// resampler of a continuous mono stream, the filter state is kept between chunks
+pub struct StreamResampler {
+    resampler: SincFixedIn<f32>,
+    input_rate: u32,
+    ratio: f64,
+    /// input not filling a whole frame yet
+    pending: Vec<f32>,
+    output: Vec<Vec<f32>>,
+    /// output of the filter delay still to be dropped
+    delay: usize,
+    /// samples taken in and given out, which cut the flushed tail
+    input_len: u64,
+    output_len: u64,
 }
 
-pub fn resample_to_16k(input: &[f32], input_sample_rate: f64) -> Vec<f32> {
-    let params = SincInterpolationParameters {
-        sinc_len: 256,
-        f_cutoff: 0.95,
-        interpolation: SincInterpolationType::Linear,
-        oversampling_factor: 256,
-        window: WindowFunction::BlackmanHarris2,
-    };
-
-    let mut resampler =
-        SincFixedIn::<f32>::new(16000. / input_sample_rate, 2.0, params, input.len(), 1).unwrap();
-
-    let waves_in = vec![input.to_vec()];
-    let mut output = resampler.process(&waves_in, None).unwrap();
-    output.remove(0)
+impl StreamResampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Result<StreamResampler, Error> {
+        let params = SincInterpolationParameters {
+            sinc_len: 256,
+            f_cutoff: 0.95,
+            interpolation: SincInterpolationType::Linear,
+            oversampling_factor: 256,
+            window: WindowFunction::BlackmanHarris2,
+        };
+
+        let ratio = output_rate as f64 / input_rate as f64;
+        let resampler = SincFixedIn::<f32>::new(ratio, 2.0, params, RESAMPLE_FRAMES, 1)?;
+        let output = resampler.output_buffer_allocate(true);
+        let delay = resampler.output_delay();
+
+        Ok(StreamResampler {
+            resampler,
+            input_rate,
+            ratio,
+            pending: Vec::with_capacity(RESAMPLE_FRAMES),
+            output,
+            delay,
+            input_len: 0,
+            output_len: 0,
+        })
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    /// resample `input`, samples not filling a whole frame wait for the next call
+    pub fn process(&mut self, input: &[f32]) -> Result<Vec<f32>, Error> {
+        self.pending.extend_from_slice(input);
+        self.input_len += input.len() as u64;
+
+        let mut output = vec![];
+        let mut offset = 0;
+        while self.pending.len() - offset >= RESAMPLE_FRAMES {
+            let frame = &self.pending[offset..offset + RESAMPLE_FRAMES];
+            let (_, len) = self
+                .resampler
+                .process_into_buffer(&[frame], &mut self.output, None)?;
+            self.take_output(len, &mut output);
+            offset += RESAMPLE_FRAMES;
+        }
+
+        self.pending.drain(..offset);
+        Ok(output)
+    }
+
+    /// resample the samples left and the filter delay at the end of the stream,
+    /// the resampler starts over afterwards
+    pub fn flush(&mut self) -> Result<Vec<f32>, Error> {
+        let expected = (self.input_len as f64 * self.ratio).round() as u64;
+        let mut output = vec![];
+
+        // the samples left go in once, then silence pushes out the delay
+        let pending = std::mem::take(&mut self.pending);
+        let mut wave_in = Some([pending.as_slice()]).filter(|_| !pending.is_empty());
+        while self.output_len < expected {
+            let wave = wave_in.take();
+            let (_, len) = self.resampler.process_partial_into_buffer(
+                wave.as_ref().map(|wave| wave.as_slice()),
+                &mut self.output,
+                None,
+            )?;
+            self.take_output(len, &mut output);
+        }
+
+        let extra = (self.output_len - expected) as usize;
+        output.truncate(output.len().saturating_sub(extra));
+
+        self.resampler.reset();
+        self.delay = self.resampler.output_delay();
+        self.input_len = 0;
+        self.output_len = 0;
+
+        Ok(output)
+    }
+
+    /// move `len` resampled samples to `output` without the filter delay
+    fn take_output(&mut self, len: usize, output: &mut Vec<f32>) {
+        let skip = self.delay.min(len);
+        self.delay -= skip;
+
+        output.extend_from_slice(&self.output[0][skip..len]);
+        self.output_len += (len - skip) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    use super::*;
+
+    #[test]
+    fn test_downmix_into() {
+        let spec = SignalSpec::new(48000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buf = AudioBuffer::<f32>::new(4, spec);
+        buf.render_reserved(Some(2));
+        buf.chan_mut(0).copy_from_slice(&[1.0, 0.5]);
+        buf.chan_mut(1).copy_from_slice(&[0.0, -0.5]);
+
+        let mut out = vec![0.25];
+        downmix_into(&buf, &mut out);
+        assert_eq!(out, vec![0.25, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_stream_resampler() {
+        let input = (0..22050)
+            .map(|i| (i as f32 * 0.05).sin())
+            .collect::<Vec<f32>>();
+
+        let mut resampler = StreamResampler::new(22050, 16000).unwrap();
+        let mut whole = resampler.process(&input).unwrap();
+        whole.extend(resampler.flush().unwrap());
+        assert_eq!(whole.len(), 16000);
+
+        // the same output whatever the chunk sizes are
+        let mut chunked = vec![];
+        for chunk in input.chunks(333) {
+            chunked.extend(resampler.process(chunk).unwrap());
+        }
+        chunked.extend(resampler.flush().unwrap());
+        assert_eq!(whole, chunked);
+    }
 }
@@ -0,0 +1,61 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    downloader::DownloaderConfig, latency::LatencyConfig, reconnect::ReconnectConfig,
+    speech::DecodeConfig, vad::VadConfig, wait::WaitConfig, Error,
+};
+
+/// settings of a toml config file, command line flags override them
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub vad: VadConfig,
+    pub whisper: DecodeConfig,
+    pub reconnect: ReconnectConfig,
+    pub downloader: DownloaderConfig,
+    pub wait: WaitConfig,
+    pub latency: LatencyConfig,
+}
+
+impl FileConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> crate::Result<FileConfig> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        Ok(toml::from_str(&content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_config() {
+        let config: FileConfig = toml::from_str(
+            r#"
+            [vad]
+            threshold = 0.6
+            min_silence_duration_ms = 500
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.vad.threshold, 0.6);
+        assert_eq!(config.vad.min_silence_duration_ms, 500);
+        assert_eq!(config.vad.min_speech_duration_ms, 300);
+        assert_eq!(config.whisper, DecodeConfig::default());
+
+        let config: FileConfig = toml::from_str("").unwrap();
+        assert_eq!(config, FileConfig::default());
+    }
+
+    #[test]
+    fn test_reject_unknown_fields() {
+        assert!(toml::from_str::<FileConfig>("[vads]\nthreshold = 0.6").is_err());
+        assert!(toml::from_str::<FileConfig>("[vad]\nthreshhold = 0.6").is_err());
+        assert!(toml::from_str::<FileConfig>("[whisper]\nbeam = 5").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader, Read},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::util::Log;
+
+/// program streaming the video to stdout
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Downloader {
+    #[default]
+    YtDlp,
+    Streamlink,
+}
+
+/// how the downloader is run
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloaderConfig {
+    pub kind: Downloader,
+    /// binary of the downloader, its name is looked up in `PATH` by default
+    pub path: Option<String>,
+    /// format of yt-dlp or stream quality of streamlink, the smallest by default
+    pub format: Option<String>,
+    /// netscape cookies file for members-only streams, yt-dlp only
+    pub cookies: Option<String>,
+    /// start live streams from the beginning instead of the live edge
+    pub live_from_start: bool,
+    /// extra arguments passed to the downloader as they are
+    pub args: Vec<String>,
+}
+
+impl DownloaderConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.kind == Downloader::Streamlink && self.cookies.is_some() {
+            return Err("cookies file is only supported by yt-dlp".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// command writing the stream of `url` to stdout.
+    /// restarted streams don't go back to the beginning, so `from_start` is only set first
+    pub fn command(&self, url: &str, from_start: bool) -> Command {
+        let mut command = Command::new(self.binary());
+        let from_start = from_start && self.live_from_start;
+
+        match self.kind {
+            Downloader::YtDlp => {
+                command
+                    .arg(url)
+                    .args(["-f", self.format.as_deref().unwrap_or("w")])
+                    .args(["--quiet"])
+                    .args(["-o", "-"]);
+                if let Some(cookies) = &self.cookies {
+                    command.args(["--cookies", cookies]);
+                }
+                if from_start {
+                    command.arg("--live-from-start");
+                }
+            }
+            Downloader::Streamlink => {
+                command
+                    .arg(url)
+                    .arg(self.format.as_deref().unwrap_or("worst"))
+                    .args(["--stdout", "--quiet"]);
+                if from_start {
+                    command.arg("--hls-live-restart");
+                }
+            }
+        }
+
+        command.args(&self.args);
+        command
+    }
+
+    /// yt-dlp command to read stream metadata with the same cookies and arguments
+    pub fn info_command(&self, url: &str) -> Command {
+        let (binary, args) = match self.kind {
+            Downloader::YtDlp => (self.binary(), self.args.as_slice()),
+            // streamlink arguments mean nothing to yt-dlp
+            Downloader::Streamlink => ("yt-dlp", &[] as &[String]),
+        };
+
+        let mut command = Command::new(binary);
+        command.arg(url);
+        if let Some(cookies) = &self.cookies {
+            command.args(["--cookies", cookies]);
+        }
+        command.args(args);
+        command
+    }
+
+    fn binary(&self) -> &str {
+        match (&self.path, self.kind) {
+            (Some(path), _) => path,
+            (None, Downloader::YtDlp) => "yt-dlp",
+            (None, Downloader::Streamlink) => "streamlink",
+        }
+    }
+}
+
+/// why the downloader failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadErrorKind {
+    /// upcoming live stream or premiere
+    NotStarted,
+    /// members-only content
+    MembersOnly,
+    Private,
+    /// removed, terminated or never existed
+    Unavailable,
+    GeoBlocked,
+    /// anything else, which may go away after a restart
+    Failed,
+}
+
+/// error reported by the downloader on its stderr
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadError {
+    pub kind: DownloadErrorKind,
+    /// the line printed by the downloader
+    pub message: String,
+}
+
+impl DownloadError {
+    pub fn new<S: Into<String>>(kind: DownloadErrorKind, message: S) -> DownloadError {
+        DownloadError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// parse a stderr line of yt-dlp or streamlink, only `ERROR:` lines are errors.
+    /// sign in prompts to confirm the age or that it's not a bot are not fatal,
+    /// they may go away after a restart or with a cookies file
+    pub fn parse(line: &str) -> Option<DownloadError> {
+        use DownloadErrorKind::*;
+
+        let lower = line.trim().to_lowercase();
+        if !lower.starts_with("error:") {
+            return None;
+        }
+
+        let contains = |patterns: &[&str]| patterns.iter().any(|p| lower.contains(p));
+
+        let kind = if contains(&["will begin in", "premieres in", "is upcoming"]) {
+            NotStarted
+        } else if contains(&["members-only", "members only", "join this channel"]) {
+            MembersOnly
+        } else if contains(&["private video", "video is private"]) {
+            Private
+        } else if contains(&["in your country", "geo restrict", "geo-restrict"]) {
+            GeoBlocked
+        } else if contains(&[
+            "video unavailable",
+            "has been removed",
+            "has been terminated",
+            "no longer available",
+            "does not exist",
+        ]) {
+            Unavailable
+        } else {
+            Failed
+        };
+
+        Some(DownloadError::new(kind, line.trim()))
+    }
+
+    /// restarting the downloader won't help
+    pub fn is_fatal(&self) -> bool {
+        self.kind != DownloadErrorKind::Failed
+    }
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use DownloadErrorKind::*;
+
+        let reason = match self.kind {
+            NotStarted => "the stream has not started yet",
+            MembersOnly => "the stream is members-only",
+            Private => "the video is private",
+            Unavailable => "the video is unavailable",
+            GeoBlocked => "the video is not available in this country",
+            Failed => "the downloader failed",
+        };
+
+        write!(f, "{}: {}", reason, self.message)
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// last error of the downloader, shared with the stderr thread
+pub type LastError = Arc<Mutex<Option<DownloadError>>>;
+
+/// read the stderr of the downloader on a dedicated thread,
+/// lines are logged and the last error is kept in `error`.
+/// the thread ends once the downloader exits
+pub fn watch_stderr<R>(stderr: R, error: LastError, logger: Log) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            match DownloadError::parse(&line) {
+                Some(e) => {
+                    logger.warn(&line);
+                    *error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+                }
+                None => logger.verbose(&line),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_downloader_command() {
+        let config = DownloaderConfig::default();
+        assert_eq!(
+            args(&config.command("id", true)),
+            ["id", "-f", "w", "--quiet", "-o", "-"]
+        );
+
+        let config = DownloaderConfig {
+            format: Some("ba".to_string()),
+            cookies: Some("cookies.txt".to_string()),
+            live_from_start: true,
+            args: vec!["--proxy".to_string(), "socks5://127.0.0.1".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            args(&config.command("id", true)),
+            [
+                "id",
+                "-f",
+                "ba",
+                "--quiet",
+                "-o",
+                "-",
+                "--cookies",
+                "cookies.txt",
+                "--live-from-start",
+                "--proxy",
+                "socks5://127.0.0.1"
+            ]
+        );
+        assert!(!args(&config.command("id", false)).contains(&"--live-from-start".to_string()));
+
+        let config = DownloaderConfig {
+            kind: Downloader::Streamlink,
+            ..Default::default()
+        };
+        assert_eq!(config.command("url", true).get_program(), "streamlink");
+        assert_eq!(
+            args(&config.command("url", true)),
+            ["url", "worst", "--stdout", "--quiet"]
+        );
+    }
+
+    #[test]
+    fn test_parse_download_error() {
+        let kind = |line| DownloadError::parse(line).map(|e| e.kind);
+
+        assert_eq!(
+            kind("ERROR: [youtube] abc: This live event will begin in 3 hours."),
+            Some(DownloadErrorKind::NotStarted)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: Join this channel to get access to members-only content"),
+            Some(DownloadErrorKind::MembersOnly)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: Private video. Sign in if you've been granted access"),
+            Some(DownloadErrorKind::Private)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: Video unavailable"),
+            Some(DownloadErrorKind::Unavailable)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: The uploader has not made this video available in your country"),
+            Some(DownloadErrorKind::GeoBlocked)
+        );
+        assert_eq!(
+            kind("error: No playable streams found on this URL"),
+            Some(DownloadErrorKind::Failed)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: Sign in to confirm you're not a bot. Use --cookies"),
+            Some(DownloadErrorKind::Failed)
+        );
+        assert_eq!(
+            kind("ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users."),
+            Some(DownloadErrorKind::Failed)
+        );
+        assert_eq!(kind("WARNING: [youtube] falling back"), None);
+        assert_eq!(
+            kind("WARNING: [youtube] abc: Video unavailable in the requested format"),
+            None
+        );
+    }
+}
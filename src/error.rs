@@ -0,0 +1,183 @@
+use std::{fmt::Display, io};
+
+use tract_onnx::prelude::TractError;
+use whisper_rs::WhisperError;
+
+use crate::{
+    audio,
+    downloader::{DownloadError, DownloadErrorKind},
+};
+
+/// thread of the transcription pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Vad,
+    Whisper,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Vad => write!(f, "vad"),
+            Stage::Whisper => write!(f, "whisper"),
+        }
+    }
+}
+
+/// failures of the transcription pipeline
+#[derive(Debug)]
+pub enum Error {
+    /// invalid arguments or config file
+    Config(String),
+    /// whisper model can't be loaded
+    Model(String),
+    /// reading the input or writing the transcript failed
+    Io(io::Error),
+    /// audio can't be decoded or resampled
+    Audio(audio::Error),
+    /// vad model failed
+    Vad(TractError),
+    /// whisper failed
+    Whisper(WhisperError),
+    /// a pipeline thread panicked
+    Thread(&'static str),
+    /// the downloader can't fetch the stream
+    Download(DownloadError),
+    /// a pipeline thread stopped with an error
+    Stage(Stage, Box<Error>),
+    /// one of several streams failed, with its label
+    Stream(String, Box<Error>),
+}
+
+impl Error {
+    /// process exit code of each failure class
+    pub fn exit_code(&self) -> u8 {
+        use Error::*;
+
+        match self {
+            Config(_) => 2,
+            Model(_) => 3,
+            Io(_) => 4,
+            Audio(_) => 5,
+            Vad(_) => 6,
+            Whisper(_) => 7,
+            Thread(_) => 8,
+            Download(e) => match e.kind {
+                DownloadErrorKind::Failed => 9,
+                DownloadErrorKind::NotStarted => 10,
+                DownloadErrorKind::MembersOnly => 11,
+                DownloadErrorKind::Private => 12,
+                DownloadErrorKind::Unavailable => 13,
+                DownloadErrorKind::GeoBlocked => 14,
+            },
+            Stage(_, e) | Stream(_, e) => e.exit_code(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Error::*;
+
+        match self {
+            Config(msg) => write!(f, "invalid config: {}", msg),
+            Model(msg) => write!(f, "failed to load model: {}", msg),
+            Io(e) => write!(f, "io error: {}", e),
+            Audio(e) => write!(f, "audio error: {}", e),
+            Vad(e) => write!(f, "vad error: {}", e),
+            Whisper(e) => write!(f, "whisper error: {:?}", e),
+            Thread(name) => write!(f, "{} thread panicked", name),
+            Download(e) => write!(f, "{}", e),
+            Stage(stage, e) => write!(f, "{} stage failed: {}", stage, e),
+            Stream(label, e) => write!(f, "{}: {}", label, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Audio(e) => Some(e),
+            Error::Vad(e) => Some(e.as_ref()),
+            Error::Whisper(e) => Some(e),
+            Error::Download(e) => Some(e),
+            Error::Stage(_, e) | Error::Stream(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        // failures of the downloader travel through the reader as io errors
+        match e.get_ref().and_then(|e| e.downcast_ref::<DownloadError>()) {
+            Some(download) => Error::Download(download.clone()),
+            None => Error::Io(e),
+        }
+    }
+}
+
+impl From<audio::Error> for Error {
+    fn from(e: audio::Error) -> Self {
+        Error::Audio(e)
+    }
+}
+
+impl From<TractError> for Error {
+    fn from(e: TractError) -> Self {
+        Error::Vad(e)
+    }
+}
+
+impl From<WhisperError> for Error {
+    fn from(e: WhisperError) -> Self {
+        Error::Whisper(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let config = Error::from(toml::from_str::<toml::Value>("=").unwrap_err());
+        assert_eq!(config.exit_code(), 2);
+
+        let io = Error::from(io::Error::other("broken"));
+        assert_eq!(io.exit_code(), 4);
+        assert_eq!(io.to_string(), "io error: broken");
+
+        assert_eq!(Error::from(audio::Error::Empty).exit_code(), 5);
+
+        let vad = Error::from(TractError::msg("no model"));
+        assert_eq!(vad.exit_code(), 6);
+        assert_eq!(vad.source().unwrap().to_string(), "no model");
+
+        let download = Error::from(io::Error::other(DownloadError::new(
+            DownloadErrorKind::Private,
+            "ERROR: Private video",
+        )));
+        assert_eq!(download.exit_code(), 12);
+
+        let stage = Error::Stage(Stage::Whisper, Box::new(Error::Model("tiny".to_string())));
+        assert_eq!(stage.exit_code(), 3);
+        assert_eq!(
+            stage.to_string(),
+            "whisper stage failed: failed to load model: tiny"
+        );
+
+        let stream = Error::Stream("alice".to_string(), Box::new(download));
+        assert_eq!(stream.exit_code(), 12);
+        assert!(stream.to_string().starts_with("alice: "));
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt::Display, time::Duration};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// how the whisper thread catches up with a stream which lags too far behind
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CatchUp {
+    /// drop the queued segments of the stream except the newest one
+    #[default]
+    Skip,
+    /// decode greedily without temperature retries or carried context
+    Fast,
+    /// transcribe with the smaller `fallback_model`
+    Fallback,
+}
+
+impl Display for CatchUp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CatchUp::Skip => write!(f, "skip"),
+            CatchUp::Fast => write!(f, "fast"),
+            CatchUp::Fallback => write!(f, "fallback"),
+        }
+    }
+}
+
+/// real time control of the whisper stage
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyConfig {
+    /// lag in ms from a segment leaving vad until its lines are out which starts catching up,
+    /// off by default. audio still waiting in the downloader and pcm queues or in vad
+    /// isn't counted, so the lag behind the live edge is larger
+    pub max_lag_ms: Option<u64>,
+    /// lag in ms under which catching up stops, half of `max_lag_ms` by default
+    pub resume_lag_ms: Option<u64>,
+    pub strategy: CatchUp,
+    /// path of a smaller whisper model for the `fallback` strategy
+    pub fallback_model: Option<String>,
+}
+
+impl LatencyConfig {
+    pub fn check(&self) -> Result<(), String> {
+        match (self.max_lag_ms, self.resume_lag_ms) {
+            (Some(max), Some(resume)) if resume >= max => {
+                Err("resume_lag_ms must be less than max_lag_ms".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// catch up state of a stream, switched with hysteresis between the lag thresholds
+#[derive(Clone, Debug)]
+pub(crate) struct LagMonitor {
+    max_lag: Option<Duration>,
+    resume_lag: Duration,
+    catching_up: bool,
+}
+
+impl LagMonitor {
+    pub fn new(config: &LatencyConfig) -> LagMonitor {
+        let max_lag_ms = config.max_lag_ms.unwrap_or(0);
+        LagMonitor {
+            max_lag: config.max_lag_ms.map(Duration::from_millis),
+            resume_lag: Duration::from_millis(config.resume_lag_ms.unwrap_or(max_lag_ms / 2)),
+            catching_up: false,
+        }
+    }
+
+    /// record the lag of the last segment, returns the new state when it changed
+    pub fn update(&mut self, lag: Duration) -> Option<bool> {
+        let max_lag = self.max_lag?;
+        let catching_up = if self.catching_up {
+            lag > self.resume_lag
+        } else {
+            lag > max_lag
+        };
+
+        if catching_up == self.catching_up {
+            return None;
+        }
+        self.catching_up = catching_up;
+        Some(catching_up)
+    }
+
+    pub fn catching_up(&self) -> bool {
+        self.catching_up
+    }
+}
+
+/// real time factor of a segment, above 1 whisper is slower than the audio
+pub(crate) fn real_time_factor(processing: Duration, audio_ms: i64) -> f64 {
+    if audio_ms <= 0 {
+        return 0.0;
+    }
+    processing.as_secs_f64() * 1000.0 / audio_ms as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lag_monitor() {
+        let secs = Duration::from_secs;
+        let mut monitor = LagMonitor::new(&LatencyConfig {
+            max_lag_ms: Some(10000),
+            ..Default::default()
+        });
+
+        assert_eq!(monitor.update(secs(8)), None);
+        assert_eq!(monitor.update(secs(12)), Some(true));
+        // keeps catching up until the lag is under the resume threshold
+        assert_eq!(monitor.update(secs(8)), None);
+        assert!(monitor.catching_up());
+        assert_eq!(monitor.update(secs(4)), Some(false));
+
+        let mut off = LagMonitor::new(&LatencyConfig::default());
+        assert_eq!(off.update(secs(60)), None);
+        assert!(!off.catching_up());
+    }
+
+    #[test]
+    fn test_check_latency_config() {
+        let config = |max_lag_ms, resume_lag_ms| LatencyConfig {
+            max_lag_ms,
+            resume_lag_ms,
+            ..Default::default()
+        };
+
+        assert!(config(Some(10000), None).check().is_ok());
+        assert!(config(Some(10000), Some(5000)).check().is_ok());
+        assert!(config(Some(10000), Some(10000)).check().is_err());
+        assert!(config(Some(10000), Some(20000)).check().is_err());
+    }
+
+    #[test]
+    fn test_real_time_factor() {
+        assert_eq!(real_time_factor(Duration::from_millis(500), 2000), 0.25);
+        assert_eq!(real_time_factor(Duration::from_secs(3), 1500), 2.0);
+        assert_eq!(real_time_factor(Duration::from_secs(1), 0), 0.0);
+    }
+
+    #[test]
+    fn test_catch_up_name() {
+        // the names of the command line values
+        for strategy in CatchUp::value_variants() {
+            let value = strategy.to_possible_value().unwrap();
+            assert_eq!(strategy.to_string(), value.get_name());
+        }
+    }
+}
@@ -0,0 +1,29 @@
+pub mod audio;
+pub mod config;
+pub mod downloader;
+mod error;
+pub mod latency;
+pub mod output;
+pub mod queue;
+pub mod reconnect;
+pub mod server;
+pub mod source;
+pub mod speech;
+pub mod stats;
+pub mod timestamp;
+mod transcriber;
+pub mod ts;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod util;
+pub mod vad;
+pub mod wait;
+#[cfg(feature = "zh")]
+pub mod zh;
+
+pub use error::{Error, Stage};
+pub use transcriber::{
+    Scheduler, Session, Transcriber, TranscriberBuilder, Transcript, TranscriptStream,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
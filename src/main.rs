@@ -1,43 +1,35 @@
-use audio::{resample_to_16k, YOUTUBE_TS_SAMPLE_RATE};
+use std::{
+    collections::HashSet,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[cfg(feature = "tui")]
+use std::sync::mpsc::{self, Sender};
+
 use clap::Parser;
 use owo_colors::OwoColorize;
-use ringbuf::{Consumer, HeapRb, LocalRb, Producer, Rb, SharedRb};
-use speech::{SpeechConfig, WhisperPayload};
-use std::{
-    error::Error,
-    ffi::c_int,
-    io::{BufRead, BufReader},
-    mem::MaybeUninit,
-    process::{Child, ChildStdout, Command, Stdio},
-    sync::{
-        mpsc::{self, Receiver, SyncSender},
-        Arc,
-    },
-    thread::{self, JoinHandle},
-    time::Instant,
+use yt_cli_live::{
+    config::FileConfig,
+    downloader::{Downloader, DownloaderConfig},
+    latency::{CatchUp, LatencyConfig},
+    output::{Output, OutputFormat},
+    queue::OverflowPolicy,
+    reconnect::ReconnectConfig,
+    server::Server,
+    source::{ChildHandle, Input, Source, StreamInfo},
+    speech::{DecodeConfig, Task},
+    stats::Stats,
+    timestamp::{Clock, TimestampMode},
+    util::Log,
+    vad::VadConfig,
+    wait::{wait_for_stream, WaitConfig},
+    Error, Session, Transcriber, Transcript,
 };
-use vad::{split_audio_data_with_window_size, VadState, WINDOW_SIZE_SAMPLES};
-use whisper_rs::WhisperContext;
-
-use util::Log;
-
-mod audio;
-mod speech;
-mod util;
-mod vad;
-#[cfg(feature = "zh")]
-mod zh;
-
-type F32Consumer = Consumer<f32, Arc<SharedRb<f32, Vec<MaybeUninit<f32>>>>>;
-type SegmentProducer =
-    Producer<vad::VadSegment, Arc<SharedRb<vad::VadSegment, Vec<MaybeUninit<vad::VadSegment>>>>>;
-type SegmentConsumer =
-    Consumer<vad::VadSegment, Arc<SharedRb<vad::VadSegment, Vec<MaybeUninit<vad::VadSegment>>>>>;
-
-enum ThreadState {
-    End,
-    Sync,
-}
+
+#[cfg(feature = "tui")]
+use yt_cli_live::tui;
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
@@ -50,225 +42,724 @@ struct Args {
     #[arg(short, long, default_value_t = 1)]
     threads: u8,
 
-    /// whisper parse target language
+    /// whisper parse target language, `auto` detects the language of each segment
     #[arg(short, long, default_value = "en")]
     lang: String,
 
-    /// youtube url or youtube video id
-    #[arg()]
-    url: String,
+    /// chinese variant to convert chinese detected in `auto` language to, like zh-tw or zh-hk
+    #[cfg(feature = "zh")]
+    #[arg(long)]
+    zh_variant: Option<String>,
+
+    /// translate the speech to english
+    #[arg(long, default_value_t = false)]
+    translate: bool,
+
+    /// print the original and the english translation of each line
+    #[arg(long, default_value_t = false)]
+    bilingual: bool,
+
+    /// youtube url, youtube video id, local audio file or `-` for stdin.
+    /// several streams share one loaded model and their lines are labeled
+    #[arg(required = true)]
+    input: Vec<String>,
+
+    /// label of the lines of each input in order, several inputs are labeled
+    /// with the video id or file name by default
+    #[arg(long)]
+    label: Vec<String>,
+
+    /// run input as a shell command and read audio from its stdout
+    #[arg(long, default_value_t = false)]
+    command: bool,
+
+    /// format of the transcript
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// write the transcript to a file instead of stdout
+    #[arg(long)]
+    output_file: Option<String>,
+
+    /// serve a caption overlay page and the lines as server-sent events at an address like 127.0.0.1:8080
+    #[arg(long)]
+    serve: Option<String>,
+
+    /// timestamp of text lines, `stream` and wall clock modes use the start time reported by yt-dlp
+    #[arg(long, value_enum, default_value_t = TimestampMode::Offset)]
+    timestamp: TimestampMode,
+
+    /// what to do with audio the model can't keep up with,
+    /// `block` for local inputs and `drop-newest` for live streams by default
+    #[arg(long, value_enum)]
+    overflow: Option<OverflowPolicy>,
+
+    /// toml config file, flags override its settings
+    #[arg(short, long)]
+    config: Option<String>,
+
+    #[command(flatten)]
+    vad: VadArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+
+    #[command(flatten)]
+    reconnect: ReconnectArgs,
+
+    #[command(flatten)]
+    downloader: DownloaderArgs,
+
+    #[command(flatten)]
+    wait: WaitArgs,
+
+    #[command(flatten)]
+    latency: LatencyArgs,
+
+    /// full screen terminal ui with a scrollable transcript and the pipeline status
+    #[cfg(feature = "tui")]
+    #[arg(long, default_value_t = false)]
+    tui: bool,
 
     /// show log of runtime
     #[arg(short, long, default_value_t = false)]
     verbose: bool,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+#[derive(clap::Args, Debug, Clone)]
+struct VadArgs {
+    /// speech probability to start a speech [default: 0.5]
+    #[arg(long)]
+    vad_threshold: Option<f32>,
+
+    /// speech probability to end a speech [default: 0.1]
+    #[arg(long)]
+    vad_neg_threshold: Option<f32>,
+
+    /// silence in ms needed after a speech to end the segment [default: 800]
+    #[arg(long)]
+    min_silence_ms: Option<u32>,
+
+    /// speech shorter than this in ms is dropped [default: 300]
+    #[arg(long)]
+    min_speech_ms: Option<u32>,
+
+    /// speech longer than this in ms is split [default: 15000]
+    #[arg(long)]
+    max_segment_ms: Option<u32>,
+
+    /// audio in ms kept before and after each speech [default: 200]
+    #[arg(long)]
+    speech_pad_ms: Option<u32>,
+}
+
+impl VadArgs {
+    fn apply(&self, config: &mut VadConfig) {
+        if let Some(threshold) = self.vad_threshold {
+            config.threshold = threshold;
+        }
+        if let Some(neg_threshold) = self.vad_neg_threshold {
+            config.neg_threshold = neg_threshold;
+        }
+        if let Some(ms) = self.min_silence_ms {
+            config.min_silence_duration_ms = ms;
+        }
+        if let Some(ms) = self.min_speech_ms {
+            config.min_speech_duration_ms = ms;
+        }
+        if let Some(ms) = self.max_segment_ms {
+            config.max_segment_duration_ms = ms;
+        }
+        if let Some(ms) = self.speech_pad_ms {
+            config.speech_pad_ms = ms;
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct DecodeArgs {
+    /// beam search with this many beams, greedy sampling below 2 [default: 0]
+    #[arg(long)]
+    beam_size: Option<i32>,
+
+    /// candidates of greedy sampling [default: 1]
+    #[arg(long)]
+    best_of: Option<i32>,
+
+    /// initial sampling temperature [default: 0.0]
+    #[arg(long)]
+    temperature: Option<f32>,
+
+    /// temperature increase when decoding fails, 0 disables the fallback [default: 0.2]
+    #[arg(long)]
+    temperature_inc: Option<f32>,
+
+    /// retry decoding with a higher compression entropy [default: 2.4]
+    #[arg(long)]
+    entropy_thold: Option<f32>,
+
+    /// retry decoding with a lower average log probability [default: -1.0]
+    #[arg(long)]
+    logprob_thold: Option<f32>,
+
+    /// text given to whisper before every segment, like names and vocabulary
+    #[arg(long)]
+    prompt: Option<String>,
+
+    /// give the text of the previous segment to whisper as context
+    #[arg(long, default_value_t = false)]
+    carry_context: bool,
+}
+
+impl DecodeArgs {
+    fn apply(&self, config: &mut DecodeConfig) {
+        if let Some(beam_size) = self.beam_size {
+            config.beam_size = beam_size;
+        }
+        if let Some(best_of) = self.best_of {
+            config.best_of = best_of;
+        }
+        if let Some(temperature) = self.temperature {
+            config.temperature = temperature;
+        }
+        if let Some(temperature_inc) = self.temperature_inc {
+            config.temperature_inc = temperature_inc;
+        }
+        if let Some(thold) = self.entropy_thold {
+            config.entropy_thold = thold;
+        }
+        if let Some(thold) = self.logprob_thold {
+            config.logprob_thold = thold;
+        }
+        if let Some(prompt) = &self.prompt {
+            config.initial_prompt = Some(prompt.clone());
+        }
+        if self.carry_context {
+            config.carry_context = true;
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ReconnectArgs {
+    /// restarts of a dropped yt-dlp stream in a row before giving up [default: 5]
+    #[arg(long)]
+    reconnect_retries: Option<u32>,
+
+    /// seconds without data from yt-dlp before restarting it [default: 30]
+    #[arg(long)]
+    stall_timeout: Option<u64>,
+}
+
+impl ReconnectArgs {
+    fn apply(&self, config: &mut ReconnectConfig) {
+        if let Some(retries) = self.reconnect_retries {
+            config.max_retries = retries;
+        }
+        if let Some(secs) = self.stall_timeout {
+            config.stall_timeout_secs = secs;
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct DownloaderArgs {
+    /// program streaming the url [default: yt-dlp]
+    #[arg(long, value_enum)]
+    downloader: Option<Downloader>,
+
+    /// binary of the downloader
+    #[arg(long)]
+    downloader_path: Option<String>,
+
+    /// yt-dlp format or streamlink quality [default: w for yt-dlp, worst for streamlink]
+    #[arg(short, long)]
+    format: Option<String>,
+
+    /// netscape cookies file for members-only streams, yt-dlp only
+    #[arg(long)]
+    cookies: Option<String>,
+
+    /// transcribe live streams from the beginning instead of the live edge
+    #[arg(long, default_value_t = false)]
+    live_from_start: bool,
+
+    /// extra argument passed to the downloader, repeat it for each argument
+    #[arg(long, num_args = 1, allow_hyphen_values = true)]
+    downloader_arg: Vec<String>,
+}
+
+impl DownloaderArgs {
+    fn apply(&self, config: &mut DownloaderConfig) {
+        if let Some(downloader) = self.downloader {
+            config.kind = downloader;
+        }
+        if let Some(path) = &self.downloader_path {
+            config.path = Some(path.clone());
+        }
+        if let Some(format) = &self.format {
+            config.format = Some(format.clone());
+        }
+        if let Some(cookies) = &self.cookies {
+            config.cookies = Some(cookies.clone());
+        }
+        if self.live_from_start {
+            config.live_from_start = true;
+        }
+        config.args.extend(self.downloader_arg.iter().cloned());
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct WaitArgs {
+    /// wait for a scheduled live stream or premiere to start instead of failing
+    #[arg(short, long, default_value_t = false)]
+    wait: bool,
+
+    /// seconds between checks of an upcoming stream [default: 60]
+    #[arg(long)]
+    wait_interval: Option<u64>,
+
+    /// give up after waiting this many seconds for the stream to start
+    #[arg(long)]
+    wait_timeout: Option<u64>,
+}
+
+impl WaitArgs {
+    fn apply(&self, config: &mut WaitConfig) {
+        if self.wait {
+            config.enable = true;
+        }
+        if let Some(secs) = self.wait_interval {
+            config.poll_interval_secs = secs;
+        }
+        if let Some(secs) = self.wait_timeout {
+            config.max_wait_secs = Some(secs);
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct LatencyArgs {
+    /// lag in ms from a segment leaving vad until its lines are out after which
+    /// whisper catches up, off by default
+    #[arg(long)]
+    max_lag_ms: Option<u64>,
+
+    /// how whisper catches up with a lagging stream [default: skip]
+    #[arg(long, value_enum)]
+    catch_up: Option<CatchUp>,
+
+    /// smaller whisper model used by the `fallback` catch up strategy
+    #[arg(long)]
+    fallback_model: Option<String>,
+}
+
+impl LatencyArgs {
+    fn apply(&self, config: &mut LatencyConfig) {
+        if let Some(ms) = self.max_lag_ms {
+            config.max_lag_ms = Some(ms);
+        }
+        if let Some(strategy) = self.catch_up {
+            config.strategy = strategy;
+        }
+        if let Some(model) = &self.fallback_model {
+            config.fallback_model = Some(model.clone());
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
-    let logger = Log::new(args.verbose);
-
-    let (mut child, stdout) = get_yt_dlp_stdout(&args.url);
-    let mut reader = BufReader::new(stdout);
-
-    // local buffer for ts file in 1Mb
-    let rb_size = 1024 * 1024;
-    let rb = LocalRb::<u8, Vec<_>>::new(rb_size);
-    let (mut prod, mut cons) = rb.split();
-
-    // shared buffer f32 transformed pcm in 30s audio data
-    let rb_size = YOUTUBE_TS_SAMPLE_RATE as usize * 30;
-    let rb = HeapRb::<f32>::new(rb_size);
-    let (mut ts_prod, ts_cons) = rb.split();
-
-    // shared buffer for vad output in 20 segment
-    let rb = HeapRb::<vad::VadSegment>::new(20);
-    let (vad_prod, vad_cons) = rb.split();
-
-    let (tx, rx) = mpsc::sync_channel::<ThreadState>(1);
-    let (vad_tx, vad_rx) = mpsc::sync_channel::<ThreadState>(1);
-
-    let handle_vad = evoke_vad_thread(args.clone(), (vad_tx.clone(), rx), (vad_prod, ts_cons));
-    let handle_whisper = evoke_whisper_thread(args, vad_rx, vad_cons);
-
-    loop {
-        let buf = reader.fill_buf()?;
-        if buf.is_empty() {
-            break;
-        }
-
-        let len = buf.len();
-        prod.push_slice(buf);
-
-        if prod.is_full() || prod.len() > 128000 {
-            let data = cons.pop_iter().collect::<Vec<u8>>();
-            logger.verbose(format!("Reading {}kb data from yt-dlp", data.len() / 1024));
-
-            match audio::get_audio_data(&data) {
-                Ok((audio_data, dur)) => {
-                    logger.verbose(format!(
-                        "Get {}kb audio data and duration {:.3}s from ts",
-                        audio_data.len() / 1024,
-                        dur
-                    ));
-
-                    ts_prod.push_slice(&audio_data);
-                    if let Err(e) = tx.try_send(ThreadState::Sync) {
-                        match e {
-                            mpsc::TrySendError::Full(_) => (),
-                            mpsc::TrySendError::Disconnected(_) => break,
-                        }
-                    }
-                }
-                Err(err) => {
-                    logger.error(err.to_string());
-                }
-            }
-        }
-
-        reader.consume(len);
+
+    match run(args) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{} {}", "[error]".red(), e.red().bold());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(args: Args) -> yt_cli_live::Result<()> {
+    let logger = cli_logger(&args);
+
+    let mut config = match &args.config {
+        Some(path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+    args.vad.apply(&mut config.vad);
+    args.decode.apply(&mut config.whisper);
+    args.reconnect.apply(&mut config.reconnect);
+    args.downloader.apply(&mut config.downloader);
+    args.wait.apply(&mut config.wait);
+    args.latency.apply(&mut config.latency);
+    config.wait.countdown = !use_tui(&args);
+    config.downloader.check().map_err(Error::Config)?;
+    config.vad.check().map_err(Error::Config)?;
+
+    let sources = args
+        .input
+        .iter()
+        .map(|input| Source::parse(input, args.command))
+        .collect::<Vec<_>>();
+    // cues of several streams would interleave with times going back and forth
+    if sources.len() > 1 && matches!(args.output_format, OutputFormat::Srt | OutputFormat::Vtt) {
+        return Err(Error::Config(
+            "srt and vtt output take a single input, use jsonl for several streams".to_string(),
+        ));
     }
 
-    tx.send(ThreadState::End).unwrap();
-    vad_tx.send(ThreadState::End).unwrap();
-    child.kill().expect("failed to kill yt-dlp process");
-    handle_vad.join().unwrap();
-    handle_whisper.join().unwrap();
+    let server = match &args.serve {
+        Some(addr) => {
+            let server = Server::bind(addr.as_str(), logger.clone())?;
+            eprintln!(
+                "{} captions at http://{}",
+                "[serve]".green(),
+                server.local_addr()
+            );
+            Some(Arc::new(server))
+        }
+        None => None,
+    };
 
+    #[cfg(feature = "tui")]
+    if args.tui {
+        return run_tui(args, config, sources, server, logger);
+    }
+
+    let output = Output::new(
+        args.output_format,
+        args.output_file.as_deref(),
+        Clock::default(),
+    )?;
+    let sink = Sink::new(output, server, logger);
+    transcribe(args, config, sources, sink)
+}
+
+/// show the transcription in the terminal ui while it runs on another thread
+#[cfg(feature = "tui")]
+fn run_tui(
+    args: Args,
+    config: FileConfig,
+    sources: Vec<Source>,
+    server: Option<Arc<Server>>,
+    logger: Log,
+) -> yt_cli_live::Result<()> {
+    let output = Output::quiet(
+        args.output_format,
+        args.output_file.as_deref(),
+        Clock::default(),
+    )?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut sink = Sink::new(output, server, logger);
+    sink.ui = Some(tx.clone());
+    let inputs = sink.inputs.clone();
+
+    let handle = thread::spawn(move || {
+        let result = transcribe(args, config, sources, sink);
+        let _ = tx.send(tui::Event::End(
+            result.as_ref().err().map(|e| e.to_string()),
+        ));
+        result
+    });
+
+    if tui::run(rx)? {
+        return handle.join().unwrap_or(Err(Error::Thread("transcription")));
+    }
+
+    // quit while running, streams waiting to start are left to exit with the process.
+    // every input is closed before the first failure is returned
+    let inputs = inputs.lock().unwrap_or_else(|e| e.into_inner());
+    inputs
+        .iter()
+        .map(|input| input.close())
+        .fold(Ok(()), |first, closed| first.and(closed))?;
     Ok(())
 }
 
-fn get_yt_dlp_stdout(url: &str) -> (Child, ChildStdout) {
-    let mut cmd = Command::new("yt-dlp");
-    cmd.arg(url)
-        .args(["-f", "w"])
-        .args(["--quiet"])
-        .args(["-o", "-"]);
+#[cfg(feature = "tui")]
+fn use_tui(args: &Args) -> bool {
+    args.tui
+}
 
-    let mut child = cmd
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("failed to execute yt-dlp");
+#[cfg(not(feature = "tui"))]
+fn use_tui(_: &Args) -> bool {
+    false
+}
 
-    let stdout = child.stdout.take().expect("invalid stdout stream");
+/// the terminal ui owns the terminal, logs would break it
+fn cli_logger(args: &Args) -> Log {
+    if use_tui(args) {
+        Log::quiet()
+    } else {
+        Log::new(args.verbose)
+    }
+}
 
-    (child, stdout)
+/// where transcribed lines and the state of the streams go
+#[derive(Clone)]
+struct Sink {
+    output: Arc<Mutex<Output>>,
+    /// inputs to close when the terminal ui quits
+    inputs: Arc<Mutex<Vec<ChildHandle>>>,
+    server: Option<Arc<Server>>,
+    #[cfg(feature = "tui")]
+    ui: Option<Sender<tui::Event>>,
+    logger: Log,
 }
 
-fn evoke_vad_thread(
-    args: Args,
-    channel: (SyncSender<ThreadState>, Receiver<ThreadState>),
-    rb: (SegmentProducer, F32Consumer),
-) -> JoinHandle<()> {
-    let logger = Log::new(args.verbose);
-    let (tx, rx) = channel;
-    let (mut prod, mut cons) = rb;
-
-    thread::spawn(move || {
-        let mut vad_state = VadState::new().unwrap();
-        let mut rb = LocalRb::<f32, Vec<_>>::new(WINDOW_SIZE_SAMPLES);
-
-        while let Ok(ThreadState::Sync) = rx.recv() {
-            if cons.is_empty() {
-                logger.error("empty pcm data");
-                continue;
-            }
-
-            let data = cons.pop_iter().collect::<Vec<f32>>();
-            let mut data = resample_to_16k(&data, YOUTUBE_TS_SAMPLE_RATE as f64);
-
-            if rb.len() > 0 {
-                data.splice(0..0, rb.pop_iter().collect::<Vec<f32>>());
-            }
-
-            let (left, right) = split_audio_data_with_window_size(data);
-            if let Some(d) = right {
-                d.iter().for_each(|d| {
-                    rb.push_overwrite(*d);
-                })
-            }
-
-            if let Some(data) = left {
-                let mut buf = vec![];
-
-                let running_calc = Instant::now();
-                data.chunks(WINDOW_SIZE_SAMPLES).for_each(|data| {
-                    let _ = vad::vad(&mut vad_state, data.to_vec(), &mut buf);
-                });
-
-                logger.verbose(format!(
-                    "vad process time: {}s, detect {} segment",
-                    running_calc.elapsed().as_secs(),
-                    buf.len()
-                ));
-
-                if !buf.is_empty() {
-                    prod.push_iter(&mut buf.into_iter());
-                    if let Err(e) = tx.try_send(ThreadState::Sync) {
-                        match e {
-                            mpsc::TrySendError::Full(_) => (),
-                            mpsc::TrySendError::Disconnected(_) => break,
-                        }
-                    }
-                }
-            }
+impl Sink {
+    fn new(output: Output, server: Option<Arc<Server>>, logger: Log) -> Sink {
+        Sink {
+            output: Arc::new(Mutex::new(output)),
+            inputs: Arc::default(),
+            server,
+            #[cfg(feature = "tui")]
+            ui: None,
+            logger,
         }
-    })
+    }
+
+    fn write(&self, transcript: Transcript) {
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = output.write(&transcript) {
+            self.logger
+                .error(format!("failed to write transcript: {}", e));
+        }
+
+        if let Some(server) = &self.server {
+            server.broadcast(&transcript);
+        }
+
+        #[cfg(feature = "tui")]
+        if let Some(ui) = &self.ui {
+            let _ = ui.send(tui::Event::Line(
+                output.format_time(&transcript),
+                transcript,
+            ));
+        }
+    }
+
+    /// a stream started transcribing
+    fn stream(&self, _label: Option<&str>, _stats: Arc<Stats>) {
+        #[cfg(feature = "tui")]
+        if let Some(ui) = &self.ui {
+            let _ = ui.send(tui::Event::Stream(_label.map(str::to_string), _stats));
+        }
+    }
+
+    /// the input of a stream is open
+    fn input(&self, label: Option<&str>, input: &Input, clock: Clock) {
+        self.output
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_clock(label, clock);
+        self.inputs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(input.handle());
+    }
+
+    /// error of a stream which didn't stop the others
+    fn error(&self, label: &str, e: &Error) {
+        #[cfg(feature = "tui")]
+        if let Some(ui) = &self.ui {
+            let _ = ui.send(tui::Event::Error(format!("{}: {}", label, e)));
+            return;
+        }
+
+        eprintln!("{} {}: {}", "[error]".red(), label, e.red().bold());
+    }
 }
 
-fn evoke_whisper_thread(
+/// transcribe the sources until they all end
+fn transcribe(
     args: Args,
-    rx: Receiver<ThreadState>,
-    mut cons: SegmentConsumer,
-) -> JoinHandle<()> {
-    let ctx = WhisperContext::new(&args.model).expect("failed to load model");
-    let logger = Log::new(args.verbose);
-
-    thread::spawn(move || {
-        let mut state = ctx.create_state().expect("failed to create state");
-        let mut streaming_time = 0.0f64;
-        let lang = if args.lang.starts_with("zh") {
-            "zh"
-        } else {
-            &args.lang
-        };
-
-        #[cfg(feature = "zh")]
-        let zh_transformer = zh::ZHTransformer::from(&args.lang);
-
-        while let Ok(ThreadState::Sync) = rx.recv() {
-            if cons.is_empty() {
-                logger.error(audio::Error::Empty.to_string());
-                continue;
-            }
-
-            cons.pop_iter().for_each(|segment| {
-                let config = SpeechConfig::new(args.threads as c_int, Some(lang));
-                let mut payload: WhisperPayload = WhisperPayload::new(&segment.data, config);
-                let running_calc = Instant::now();
-
-                let segment_time = (streaming_time * 1000.0) as i64;
-                streaming_time += segment.duration as f64;
-
-                speech::process(&mut state, &mut payload, &mut |segment, start| {
-                    #[cfg(feature = "zh")]
-                    let segment = if let Ok(zh) = &zh_transformer {
-                        zh.convert(segment)
-                    } else {
-                        segment.to_string()
-                    };
-
-                    println!(
-                        "[{}] {}",
-                        util::format_timestamp_to_time(segment_time + start).bright_yellow(),
-                        segment
-                    );
-                });
-
-                logger.verbose(format!(
-                    "whisper process time: {}s",
-                    running_calc.elapsed().as_secs()
-                ));
+    config: FileConfig,
+    sources: Vec<Source>,
+    sink: Sink,
+) -> yt_cli_live::Result<()> {
+    // a labeled single input runs like one of several streams
+    if sources.len() > 1 || !args.label.is_empty() {
+        return run_streams(args, config, sources, sink);
+    }
+
+    let source = &sources[0];
+    let transcriber = build_transcriber(&args, &config, overflow_policy(&args, &config, source))?;
+    let session = transcriber.start({
+        let sink = sink.clone();
+        move |transcript| sink.write(transcript)
+    });
+
+    run_stream(source, None, session, &args, &config, &sink)
+}
+
+/// transcribe several streams at once with one whisper model,
+/// a failing stream doesn't stop the others
+fn run_streams(
+    args: Args,
+    config: FileConfig,
+    sources: Vec<Source>,
+    sink: Sink,
+) -> yt_cli_live::Result<()> {
+    let labels = stream_labels(&sources, &args.label)?;
+
+    // every stream gets the overflow policy of its own kind
+    let transcriber = build_transcriber(&args, &config, OverflowPolicy::default())?;
+    let mut scheduler = transcriber.scheduler({
+        let sink = sink.clone();
+        move |transcript| sink.write(transcript)
+    });
+
+    let handles = sources
+        .into_iter()
+        .zip(labels)
+        .map(|(source, label)| {
+            let session = scheduler.start_with(&label, overflow_policy(&args, &config, &source));
+            let (args, config, sink) = (args.clone(), config.clone(), sink.clone());
+
+            let name = label.clone();
+            let handle = thread::spawn(move || {
+                run_stream(&source, Some(&name), session, &args, &config, &sink)
             });
+            (label, handle)
+        })
+        .collect::<Vec<_>>();
+
+    let mut result = Ok(());
+    for (label, handle) in handles {
+        let stream = handle.join().unwrap_or(Err(Error::Thread("stream")));
+        match (stream, &result) {
+            (Err(e), Ok(_)) => result = Err(Error::Stream(label, Box::new(e))),
+            // only the first error is returned
+            (Err(e), Err(_)) => sink.error(&label, &e),
+            _ => (),
+        }
+    }
+
+    result.and(scheduler.finish())
+}
+
+/// open a stream and feed it into its session until it ends
+fn run_stream(
+    source: &Source,
+    label: Option<&str>,
+    mut session: Session,
+    args: &Args,
+    config: &FileConfig,
+    sink: &Sink,
+) -> yt_cli_live::Result<()> {
+    sink.stream(label, session.stats());
+
+    let mut feed = || -> yt_cli_live::Result<()> {
+        let info = stream_info(source, args, config, &sink.logger)?;
+        let mut input =
+            source.open_with(&config.downloader, &config.reconnect, sink.logger.clone())?;
+        let clock = Clock::new(
+            args.timestamp,
+            info.as_ref(),
+            config.downloader.live_from_start,
+        );
+        sink.input(label, &input, clock);
+
+        let result = session.feed(&mut input);
+        let closed = input.close();
+        result.and(closed.map_err(Into::into))
+    };
+
+    let result = feed();
+    result.and(session.finish())
+}
+
+/// labels given with `--label` in order, then the default label of each source
+fn stream_labels(sources: &[Source], labels: &[String]) -> yt_cli_live::Result<Vec<String>> {
+    if labels.len() > sources.len() {
+        return Err(Error::Config("more labels than inputs".to_string()));
+    }
+
+    let labels = sources
+        .iter()
+        .enumerate()
+        .map(|(i, source)| labels.get(i).cloned().unwrap_or_else(|| source.label()))
+        .collect::<Vec<_>>();
+
+    let mut unique = HashSet::new();
+    if let Some(label) = labels.iter().find(|label| !unique.insert(*label)) {
+        return Err(Error::Config(format!(
+            "streams share the label {}, set them with --label",
+            label
+        )));
+    }
+
+    Ok(labels)
+}
+
+/// metadata of youtube streams, which waits for upcoming streams in wait mode
+fn stream_info(
+    source: &Source,
+    args: &Args,
+    config: &FileConfig,
+    logger: &Log,
+) -> yt_cli_live::Result<Option<StreamInfo>> {
+    let info = match source {
+        // the stream metadata of the actual start is used by the clock
+        Source::YtDlp(url) if config.wait.enable => Some(wait_for_stream(
+            url,
+            &config.downloader,
+            &config.wait,
+            logger,
+        )?),
+        Source::YtDlp(url) if args.timestamp != TimestampMode::Offset => {
+            StreamInfo::fetch(url, &config.downloader)
+                .map_err(|e| logger.error(e.to_string()))
+                .ok()
+        }
+        _ => None,
+    };
+
+    Ok(info)
+}
+
+/// `--overflow`, or what suits the source when it's not given
+fn overflow_policy(args: &Args, config: &FileConfig, source: &Source) -> OverflowPolicy {
+    // local inputs and recorded videos wait for the pipeline instead of dropping audio
+    args.overflow.unwrap_or_else(|| {
+        if source.is_live(&config.downloader) {
+            OverflowPolicy::DropNewest
+        } else {
+            OverflowPolicy::Block
         }
     })
 }
+
+fn build_transcriber(
+    args: &Args,
+    config: &FileConfig,
+    overflow: OverflowPolicy,
+) -> yt_cli_live::Result<Transcriber> {
+    let task = if args.bilingual {
+        Task::Both
+    } else if args.translate {
+        Task::Translate
+    } else {
+        Task::Transcribe
+    };
+
+    let transcriber = Transcriber::builder()
+        .model(&args.model)
+        .threads(args.threads)
+        .lang(&args.lang)
+        .task(task)
+        .vad(config.vad.clone())
+        .decode(config.whisper.clone())
+        .latency(config.latency.clone())
+        .logger(cli_logger(args))
+        .overflow(overflow);
+
+    #[cfg(feature = "zh")]
+    let transcriber = transcriber.zh_variant(args.zh_variant.as_deref());
+
+    transcriber.build()
+}
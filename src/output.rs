@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use clap::ValueEnum;
+use owo_colors::OwoColorize;
+
+use crate::{timestamp::Clock, util, Transcript};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `[time] text` lines
+    #[default]
+    Text,
+    /// SubRip subtitle
+    Srt,
+    /// WebVTT subtitle
+    Vtt,
+    /// one json object per line
+    Jsonl,
+}
+
+pub struct Output {
+    format: OutputFormat,
+    writer: Box<dyn Write + Send>,
+    /// print colored text lines to stdout while writing to a file
+    echo: bool,
+    /// nothing is printed to stdout, which is taken by the terminal ui
+    quiet: bool,
+    index: usize,
+    /// timestamps of text lines
+    clock: Clock,
+    /// timestamps of the lines of labeled streams
+    stream_clocks: HashMap<String, Clock>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, path: Option<&str>, clock: Clock) -> io::Result<Output> {
+        Output::create(format, path, clock, false)
+    }
+
+    /// output which only writes the file at `path` if there is one and never prints to stdout
+    pub fn quiet(format: OutputFormat, path: Option<&str>, clock: Clock) -> io::Result<Output> {
+        Output::create(format, path, clock, true)
+    }
+
+    fn create(
+        format: OutputFormat,
+        path: Option<&str>,
+        clock: Clock,
+        quiet: bool,
+    ) -> io::Result<Output> {
+        let (writer, echo): (Box<dyn Write + Send>, bool) = match path {
+            Some(path) => (Box::new(BufWriter::new(File::create(path)?)), !quiet),
+            None if quiet => (Box::new(io::sink()), false),
+            None => (Box::new(io::stdout()), false),
+        };
+
+        Output::with_writer(format, writer, echo, quiet, clock)
+    }
+
+    fn with_writer(
+        format: OutputFormat,
+        writer: Box<dyn Write + Send>,
+        echo: bool,
+        quiet: bool,
+        clock: Clock,
+    ) -> io::Result<Output> {
+        let mut output = Output {
+            format,
+            writer,
+            echo,
+            quiet,
+            index: 0,
+            clock,
+            stream_clocks: HashMap::new(),
+        };
+
+        if format == OutputFormat::Vtt {
+            writeln!(output.writer, "WEBVTT")?;
+            writeln!(output.writer)?;
+            output.writer.flush()?;
+        }
+
+        Ok(output)
+    }
+
+    /// timestamps of the lines of the stream labeled `stream`, or of unlabeled lines
+    pub fn set_clock(&mut self, stream: Option<&str>, clock: Clock) {
+        match stream {
+            Some(stream) => {
+                self.stream_clocks.insert(stream.to_string(), clock);
+            }
+            None => self.clock = clock,
+        }
+    }
+
+    /// displayed timestamp of a line
+    pub fn format_time(&self, cue: &Transcript) -> String {
+        self.clock(cue).format(cue.start)
+    }
+
+    fn clock(&self, cue: &Transcript) -> &Clock {
+        cue.stream
+            .as_ref()
+            .and_then(|stream| self.stream_clocks.get(stream))
+            .unwrap_or(&self.clock)
+    }
+
+    /// write a cue and flush it so players can load the growing file
+    pub fn write(&mut self, cue: &Transcript) -> io::Result<()> {
+        self.index += 1;
+
+        if self.echo {
+            self.print_text(cue);
+        }
+
+        match self.format {
+            OutputFormat::Text if !self.echo && !self.quiet => {
+                self.print_text(cue);
+                return Ok(());
+            }
+            OutputFormat::Text => {
+                let time = self.clock(cue).format(cue.start);
+                let stream = stream_prefix(cue);
+                match cue.language_probability {
+                    Some(p) => writeln!(
+                        self.writer,
+                        "[{}] {}({} {:.2}) {}",
+                        time, stream, cue.language, p, cue.text
+                    )?,
+                    None => writeln!(self.writer, "[{}] {}{}", time, stream, cue.text)?,
+                }
+                if let Some(translation) = &cue.translation {
+                    writeln!(self.writer, "[{}] {}", time, translation)?;
+                }
+            }
+            OutputFormat::Srt => {
+                writeln!(self.writer, "{}", self.index)?;
+                writeln!(
+                    self.writer,
+                    "{} --> {}",
+                    format_cue_time(cue.start, ','),
+                    format_cue_time(cue.end, ',')
+                )?;
+                writeln!(self.writer, "{}{}", stream_prefix(cue), cue.text)?;
+                if let Some(translation) = &cue.translation {
+                    writeln!(self.writer, "{}", translation)?;
+                }
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Vtt => {
+                writeln!(
+                    self.writer,
+                    "{} --> {}",
+                    format_cue_time(cue.start, '.'),
+                    format_cue_time(cue.end, '.')
+                )?;
+                let text = format!("{}{}", stream_prefix(cue), cue.text);
+                writeln!(self.writer, "{}", escape_vtt(&text))?;
+                if let Some(translation) = &cue.translation {
+                    writeln!(self.writer, "{}", escape_vtt(translation))?;
+                }
+                writeln!(self.writer)?;
+            }
+            OutputFormat::Jsonl => {
+                serde_json::to_writer(&mut self.writer, cue)?;
+                writeln!(self.writer)?;
+            }
+        }
+
+        self.writer.flush()
+    }
+
+    fn print_text(&self, cue: &Transcript) {
+        let time = self.clock(cue).format(cue.start);
+        let stream = stream_prefix(cue);
+
+        match cue.language_probability {
+            Some(p) => println!(
+                "[{}] {}{} {}",
+                time.bright_yellow(),
+                stream.bright_magenta(),
+                format!("({} {:.2})", cue.language, p).bright_black(),
+                cue.text
+            ),
+            None => println!(
+                "[{}] {}{}",
+                time.bright_yellow(),
+                stream.bright_magenta(),
+                cue.text
+            ),
+        }
+
+        if let Some(translation) = &cue.translation {
+            println!("[{}] {}", time.bright_yellow(), translation.bright_cyan());
+        }
+    }
+}
+
+/// `[label] ` of lines from a labeled stream
+fn stream_prefix(cue: &Transcript) -> String {
+    cue.stream
+        .as_ref()
+        .map(|stream| format!("[{}] ", stream))
+        .unwrap_or_default()
+}
+
+/// vtt cue text is markup, `&`, `<` and `>` are written as character references
+fn escape_vtt(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `HH:MM:SS<sep>mmm` used by srt (`,`) and vtt (`.`)
+fn format_cue_time(ms: i64, sep: char) -> String {
+    util::format_timestamp(ms, sep)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// writer whose bytes stay readable after it's boxed into an output
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// everything written for `cues` in `format`
+    fn write_all(format: OutputFormat, cues: &[Transcript]) -> String {
+        let buffer = Buffer::default();
+        let mut output = Output::with_writer(
+            format,
+            Box::new(buffer.clone()),
+            false,
+            true,
+            Clock::default(),
+        )
+        .unwrap();
+        for cue in cues {
+            output.write(cue).unwrap();
+        }
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_write_srt() {
+        let translated = Transcript {
+            translation: Some("hi".to_string()),
+            ..Transcript::test("你好", 1500, 3000)
+        };
+        let srt = write_all(
+            OutputFormat::Srt,
+            &[Transcript::test("hello", 0, 1200), translated],
+        );
+
+        assert_eq!(
+            srt,
+            "1\n00:00:00,000 --> 00:00:01,200\nhello\n\n\
+             2\n00:00:01,500 --> 00:00:03,000\n你好\nhi\n\n"
+        );
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let labeled = Transcript {
+            stream: Some("a".to_string()),
+            language_probability: Some(0.5),
+            ..Transcript::test("world", 1500, 3000)
+        };
+        let jsonl = write_all(
+            OutputFormat::Jsonl,
+            &[Transcript::test("hello", 0, 1200), labeled],
+        );
+        let lines = jsonl
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lines[0],
+            serde_json::json!({
+                "start": 0,
+                "end": 1200,
+                "text": "hello",
+                "language": "en",
+                "segment": 0,
+                "tokens": [],
+            })
+        );
+        // optional fields only appear when they are set
+        assert_eq!(lines[1]["stream"], "a");
+        assert_eq!(lines[1]["language_probability"], 0.5);
+        assert!(lines[1].get("translation").is_none());
+    }
+
+    #[test]
+    fn test_write_vtt() {
+        let vtt = write_all(OutputFormat::Vtt, &[Transcript::test("hello", 0, 1200)]);
+        assert_eq!(vtt, "WEBVTT\n\n00:00:00.000 --> 00:00:01.200\nhello\n\n");
+
+        // the header is written before the first cue
+        assert_eq!(write_all(OutputFormat::Vtt, &[]), "WEBVTT\n\n");
+
+        // markup characters of the text don't break the cue
+        let vtt = write_all(
+            OutputFormat::Vtt,
+            &[Transcript::test("a <b> & c --> d", 0, 1200)],
+        );
+        assert!(vtt.ends_with("\na &lt;b&gt; &amp; c --&gt; d\n\n"));
+    }
+
+    #[test]
+    fn test_format_cue_time() {
+        let time = 1000 * 60 * 60 * 3 + 1000 * 60 * 3 + 1000 * 4 + 5;
+        assert_eq!(format_cue_time(time, ','), "03:03:04,005");
+        assert_eq!(format_cue_time(time, '.'), "03:03:04.005");
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex, MutexGuard},
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// what a full pipeline buffer does with new data
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// wait for free space, nothing is lost but live streams fall behind
+    Block,
+    /// drop the oldest queued data to make room
+    DropOldest,
+    /// drop the new data which doesn't fit
+    #[default]
+    DropNewest,
+    /// drop everything queued and continue from the newest data
+    SkipToLive,
+}
+
+/// size of a queued item counted against the capacity of its queue
+pub(crate) trait Weight {
+    fn weight(&self) -> usize {
+        1
+    }
+
+    /// made by `push_marked` for dropped items
+    fn is_marker(&self) -> bool {
+        false
+    }
+}
+
+/// the queue was closed by one of its stages
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Closed;
+
+/// owned buffers passed from one pipeline stage to the next,
+/// the overflow policy applies when it's full.
+///
+/// either side closes it: the producer at the end of the stream, after which the consumer
+/// still receives the queued items, or the consumer when it stops, which fails later pushes
+pub(crate) struct BoundedQueue<T> {
+    state: Mutex<State<T>>,
+    /// signaled on every push, pop and close
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    /// total weight of `items`
+    len: usize,
+    closed: bool,
+}
+
+impl<T: Weight> State<T> {
+    fn pop(&mut self) -> Option<T> {
+        let item = self.items.pop_front()?;
+        self.len -= item.weight();
+        Some(item)
+    }
+
+    /// the newest item when it is a marker
+    fn pop_marker(&mut self) -> Option<T> {
+        if !self.items.back()?.is_marker() {
+            return None;
+        }
+        let item = self.items.pop_back()?;
+        self.len -= item.weight();
+        Some(item)
+    }
+
+    fn take(&mut self) -> Vec<T> {
+        self.len = 0;
+        self.items.drain(..).collect()
+    }
+}
+
+impl<T: Weight> BoundedQueue<T> {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> BoundedQueue<T> {
+        BoundedQueue {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                len: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    /// push `item` and get the items the policy dropped for it, queued or the item itself.
+    /// `Block` waits until it fits, an item larger than the capacity only fits an empty queue
+    pub fn push(&self, item: T) -> Result<Vec<T>, Closed> {
+        self.push_with(item, None::<fn(&[T]) -> T>)
+    }
+
+    /// push `item` like `push`, `mark` makes a marker of the dropped items which is queued
+    /// where they were, so the consumer learns about the gap.
+    /// a marker of weight 0 always fits, dropped markers are passed to the next one
+    /// and a marker at the end of the queue grows with the items dropped after it
+    pub fn push_marked<M>(&self, item: T, mark: M) -> Result<Vec<T>, Closed>
+    where
+        M: FnOnce(&[T]) -> T,
+    {
+        self.push_with(item, Some(mark))
+    }
+
+    fn push_with<M>(&self, item: T, mark: Option<M>) -> Result<Vec<T>, Closed>
+    where
+        M: FnOnce(&[T]) -> T,
+    {
+        let weight = item.weight();
+        let mut state = self.lock();
+        let mut dropped = vec![];
+
+        loop {
+            if state.closed {
+                return Err(Closed);
+            }
+            if state.items.is_empty() || weight == 0 || state.len + weight <= self.capacity {
+                break;
+            }
+
+            match self.policy {
+                OverflowPolicy::Block => {
+                    state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+                OverflowPolicy::DropNewest => {
+                    let Some(mark) = mark else {
+                        return Ok(vec![item]);
+                    };
+
+                    // the gap follows the queued items, one left by earlier drops grows
+                    let mut gap = Vec::from_iter(state.pop_marker());
+                    gap.push(item);
+                    let marker = mark(&gap);
+                    state.len += marker.weight();
+                    state.items.push_back(marker);
+                    self.changed.notify_all();
+                    return Ok(gap.split_off(gap.len() - 1));
+                }
+                OverflowPolicy::DropOldest => dropped.extend(state.pop()),
+                OverflowPolicy::SkipToLive => dropped.extend(state.take()),
+            }
+        }
+
+        // the gap precedes the items left in the queue
+        if let Some(mark) = mark.filter(|_| !dropped.is_empty()) {
+            let marker = mark(&dropped);
+            state.len += marker.weight();
+            state.items.push_front(marker);
+        }
+        state.len += weight;
+        state.items.push_back(item);
+        self.changed.notify_all();
+        Ok(dropped)
+    }
+
+    /// wait for the next item, none once the queue is closed and empty
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.pop() {
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// next item without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        let item = self.lock().pop();
+        self.changed.notify_all();
+        item
+    }
+
+    /// take every queued item but the newest without waiting
+    pub fn drain_but_last(&self) -> Vec<T> {
+        let mut state = self.lock();
+        let len = state.items.len().saturating_sub(1);
+        let items = (0..len).filter_map(|_| state.pop()).collect();
+        drop(state);
+
+        self.changed.notify_all();
+        items
+    }
+
+    /// refuse further pushes and wake the waiting stages
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// closed and every item was received
+    pub fn is_finished(&self) -> bool {
+        let state = self.lock();
+        state.closed && state.items.is_empty()
+    }
+
+    /// total weight of the queued items
+    pub fn len(&self) -> usize {
+        self.lock().len
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().items.is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    impl Weight for u8 {}
+
+    impl<T: Weight> BoundedQueue<T> {
+        fn take_all(&self) -> Vec<T> {
+            self.lock().take()
+        }
+    }
+
+    impl Weight for Vec<u8> {
+        fn weight(&self) -> usize {
+            self.len()
+        }
+
+        fn is_marker(&self) -> bool {
+            self.is_empty()
+        }
+    }
+
+    fn queue(policy: OverflowPolicy) -> BoundedQueue<u8> {
+        let queue = BoundedQueue::new(4, policy);
+        for i in 1..=4 {
+            assert_eq!(queue.push(i), Ok(vec![]));
+        }
+        queue
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let drop_newest = queue(OverflowPolicy::DropNewest);
+        assert_eq!(drop_newest.push(5), Ok(vec![5]));
+        assert_eq!(drop_newest.take_all(), [1, 2, 3, 4]);
+
+        let drop_oldest = queue(OverflowPolicy::DropOldest);
+        assert_eq!(drop_oldest.push(5), Ok(vec![1]));
+        assert_eq!(drop_oldest.take_all(), [2, 3, 4, 5]);
+
+        let skip = queue(OverflowPolicy::SkipToLive);
+        assert_eq!(skip.push(5), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(skip.take_all(), [5]);
+    }
+
+    #[test]
+    fn test_drain_but_last() {
+        let queue = queue(OverflowPolicy::Block);
+        assert_eq!(queue.drain_but_last(), [1, 2, 3]);
+        assert_eq!(queue.take_all(), [4]);
+        assert!(queue.drain_but_last().is_empty());
+
+        // a closed queue keeps the newest item for the consumer
+        assert_eq!(queue.push(5), Ok(vec![]));
+        assert_eq!(queue.push(6), Ok(vec![]));
+        queue.close();
+        assert_eq!(queue.drain_but_last(), [5]);
+        assert_eq!(queue.recv(), Some(6));
+        assert!(queue.is_finished());
+    }
+
+    #[test]
+    fn test_weight() {
+        let queue = BoundedQueue::new(4, OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(vec![1, 2]), Ok(vec![]));
+        assert_eq!(queue.push(vec![3]), Ok(vec![]));
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.push(vec![4, 5]), Ok(vec![vec![1, 2]]));
+        assert_eq!(queue.len(), 3);
+
+        // larger than the capacity, only an empty queue takes it
+        assert_eq!(
+            queue.push(vec![6, 7, 8, 9, 10]),
+            Ok(vec![vec![3], vec![4, 5]])
+        );
+        assert_eq!(queue.len(), 5);
+    }
+
+    #[test]
+    fn test_push_marked() {
+        // an empty marker of weight 0 in place of the dropped items
+        let mark = |_: &[Vec<u8>]| vec![];
+
+        let drop_newest = BoundedQueue::new(4, OverflowPolicy::DropNewest);
+        assert_eq!(drop_newest.push_marked(vec![1, 2, 3], mark), Ok(vec![]));
+        assert_eq!(
+            drop_newest.push_marked(vec![4, 5], mark),
+            Ok(vec![vec![4, 5]])
+        );
+        assert_eq!(drop_newest.push_marked(vec![6], mark), Ok(vec![]));
+        assert_eq!(drop_newest.take_all(), [vec![1, 2, 3], vec![], vec![6]]);
+
+        let drop_oldest = BoundedQueue::new(4, OverflowPolicy::DropOldest);
+        assert_eq!(drop_oldest.push_marked(vec![1, 2], mark), Ok(vec![]));
+        assert_eq!(drop_oldest.push_marked(vec![3, 4], mark), Ok(vec![]));
+        assert_eq!(drop_oldest.push_marked(vec![5], mark), Ok(vec![vec![1, 2]]));
+        assert_eq!(drop_oldest.take_all(), [vec![], vec![3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn test_grow_trailing_marker() {
+        let mark = |_: &[Vec<u8>]| vec![];
+        let queue = BoundedQueue::new(4, OverflowPolicy::DropNewest);
+        assert_eq!(queue.push_marked(vec![1, 2, 3, 4], mark), Ok(vec![]));
+
+        // every drop lands in the one marker behind the queued items
+        for i in 5..10 {
+            assert_eq!(queue.push_marked(vec![i], mark), Ok(vec![vec![i]]));
+        }
+        assert_eq!(queue.take_all(), [vec![1, 2, 3, 4], vec![]]);
+    }
+
+    #[test]
+    fn test_block_and_close() {
+        let queue = Arc::new(queue(OverflowPolicy::Block));
+
+        let consumer = thread::spawn({
+            let queue = queue.clone();
+            move || {
+                let mut items = vec![];
+                while let Some(item) = queue.recv() {
+                    items.push(item);
+                }
+                items
+            }
+        });
+
+        // waits for the consumer instead of dropping
+        for i in 5..=8 {
+            assert_eq!(queue.push(i), Ok(vec![]));
+        }
+        queue.close();
+
+        assert_eq!(consumer.join().unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(queue.is_finished());
+        assert_eq!(queue.push(9), Err(Closed));
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    io::{self, Read},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    downloader::{watch_stderr, DownloadError, DownloadErrorKind, DownloaderConfig, LastError},
+    source::{ChildHandle, StreamInfo},
+    util::Log,
+};
+
+/// read size of the child stdout
+const CHUNK_SIZE: usize = 64 * 1024;
+/// chunks read ahead of the demuxer
+const QUEUE_SIZE: usize = 16;
+
+/// how a dropped yt-dlp stream is restarted
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// restarts in a row without receiving data before giving up
+    pub max_retries: u32,
+    /// seconds without data before the stream is considered stalled
+    pub stall_timeout_secs: u64,
+    /// longest wait in seconds between restarts
+    pub max_backoff_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_retries: 5,
+            stall_timeout_secs: 30,
+            max_backoff_secs: 30,
+        }
+    }
+}
+
+/// chunks of the downloader waiting to be read
+#[derive(Clone, Default)]
+pub struct InputQueue(Arc<AtomicUsize>);
+
+impl InputQueue {
+    /// queued chunks and the size of the queue
+    pub fn level(&self) -> (usize, usize) {
+        (self.0.load(Ordering::Relaxed), QUEUE_SIZE)
+    }
+
+    fn push(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn pop(&self) {
+        // chunks of a killed downloader may be counted after a restart reset the queue
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+
+    fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+/// stream time lost while the downloader was restarted, not taken by the pipeline yet
+#[derive(Clone, Default)]
+pub struct LostTime(Arc<Mutex<Duration>>);
+
+impl LostTime {
+    /// the lost time since the last call
+    pub fn take(&self) -> Duration {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn add(&self, lost: Duration) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) += lost;
+    }
+}
+
+/// byte stream of the downloader which restarts it when it exits or stalls while the stream is live,
+/// readers see one continuous stream
+pub struct Reconnect {
+    url: String,
+    downloader: DownloaderConfig,
+    config: ReconnectConfig,
+    child: ChildHandle,
+    rx: Mutex<Receiver<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    retries: u32,
+    /// the stream delivered data at least once
+    received: bool,
+    /// last error printed by the running downloader
+    error: LastError,
+    /// stderr thread of the running downloader
+    stderr: Option<JoinHandle<()>>,
+    queue: InputQueue,
+    /// restarts continue from the live edge, the time without data is lost
+    lost: LostTime,
+    /// start of the outage which a restart is recovering from
+    outage: Option<Instant>,
+    logger: Log,
+}
+
+impl Reconnect {
+    pub fn new(
+        url: &str,
+        downloader: DownloaderConfig,
+        config: ReconnectConfig,
+        child: ChildHandle,
+        logger: Log,
+    ) -> io::Result<Reconnect> {
+        let error = LastError::default();
+        let queue = InputQueue::default();
+        let (rx, stderr) = spawn(&downloader, url, true, &child, &error, &queue, &logger)?;
+
+        Ok(Reconnect {
+            url: url.to_string(),
+            downloader,
+            config,
+            child,
+            rx: Mutex::new(rx),
+            chunk: vec![],
+            pos: 0,
+            retries: 0,
+            received: false,
+            error,
+            stderr: Some(stderr),
+            queue,
+            lost: LostTime::default(),
+            outage: None,
+            logger,
+        })
+    }
+
+    pub fn queue(&self) -> InputQueue {
+        self.queue.clone()
+    }
+
+    pub fn lost_time(&self) -> LostTime {
+        self.lost.clone()
+    }
+
+    /// restart the downloader after a backoff, returns false when it should not be restarted
+    fn reconnect(&mut self) -> bool {
+        if self.child.is_closed() || self.retries >= self.config.max_retries {
+            return false;
+        }
+
+        let _ = self.child.kill();
+
+        let backoff = backoff_secs(self.retries, self.config.max_backoff_secs);
+        self.retries += 1;
+        self.logger.error(format!(
+            "stream dropped, reconnecting in {}s ({}/{})",
+            backoff, self.retries, self.config.max_retries
+        ));
+        thread::sleep(Duration::from_secs(backoff));
+
+        match spawn(
+            &self.downloader,
+            &self.url,
+            false,
+            &self.child,
+            &self.error,
+            &self.queue,
+            &self.logger,
+        ) {
+            Ok((rx, stderr)) => {
+                *self.rx.get_mut().unwrap_or_else(|e| e.into_inner()) = rx;
+                self.stderr = Some(stderr);
+                true
+            }
+            Err(e) => {
+                self.logger
+                    .error(format!("failed to restart the downloader: {}", e));
+                // the next read times out or disconnects and tries again
+                let (_, rx) = mpsc::sync_channel(0);
+                *self.rx.get_mut().unwrap_or_else(|e| e.into_inner()) = rx;
+                true
+            }
+        }
+    }
+
+    fn last_error(&self) -> Option<DownloadError> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// an exited or stalled downloader is only restarted while the stream is still live
+    /// and it didn't fail for a reason a restart can't fix
+    fn is_live(&self) -> bool {
+        if self.last_error().is_some_and(|e| e.is_fatal()) {
+            return false;
+        }
+
+        match StreamInfo::fetch(&self.url, &self.downloader) {
+            Ok(info) => info.is_live(),
+            // keep trying a stream which already played when the metadata can't be fetched,
+            // a stream which never delivered data most likely doesn't exist
+            Err(_) => self.received,
+        }
+    }
+
+    /// end of the stream, a failure of the downloader is returned as an error
+    /// when it is fatal, came before any data or outlasted the retries.
+    /// used up retries are an error even when the downloader only stalled
+    fn end(&self) -> io::Result<usize> {
+        if self.child.is_closed() {
+            return Ok(0);
+        }
+
+        let gave_up = self.retries >= self.config.max_retries;
+        match self.last_error() {
+            Some(e) if e.is_fatal() || !self.received || gave_up => Err(io::Error::other(e)),
+            None if gave_up => Err(io::Error::other(DownloadError::new(
+                DownloadErrorKind::Failed,
+                format!("stream stalled, gave up after {} restarts", self.retries),
+            ))),
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Read for Reconnect {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.chunk.len() {
+                let len = buf.len().min(self.chunk.len() - self.pos);
+                buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+                self.pos += len;
+                return Ok(len);
+            }
+
+            let timeout = Duration::from_secs(self.config.stall_timeout_secs);
+            let started = Instant::now();
+            let received = self
+                .rx
+                .get_mut()
+                .unwrap_or_else(|e| e.into_inner())
+                .recv_timeout(timeout);
+
+            let reconnected = match received {
+                Ok(chunk) => {
+                    // the restarted downloader delivers again
+                    if let Some(outage) = self.outage.take() {
+                        self.lost.add(outage.elapsed());
+                    }
+                    self.queue.pop();
+                    self.chunk = chunk;
+                    self.pos = 0;
+                    self.retries = 0;
+                    self.received = true;
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.logger.error(format!(
+                        "no data from the downloader for {}s",
+                        started.elapsed().as_secs()
+                    ));
+                    // a finished video may just stall at its end
+                    !self.child.is_closed() && self.is_live() && self.reconnect()
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    // the last lines of stderr tell why the downloader exited
+                    if let Some(stderr) = self.stderr.take() {
+                        let _ = stderr.join();
+                    }
+                    !self.child.is_closed() && self.is_live() && self.reconnect()
+                }
+            };
+
+            if !reconnected {
+                return self.end();
+            }
+            self.outage.get_or_insert(started);
+        }
+    }
+}
+
+/// wait before a restart, doubling from 1s up to `max`
+fn backoff_secs(retries: u32, max: u64) -> u64 {
+    (1u64 << retries.min(16)).min(max)
+}
+
+/// start the downloader and forward its stdout from a reader thread
+fn spawn(
+    downloader: &DownloaderConfig,
+    url: &str,
+    from_start: bool,
+    handle: &ChildHandle,
+    error: &LastError,
+    queue: &InputQueue,
+    logger: &Log,
+) -> io::Result<(Receiver<Vec<u8>>, JoinHandle<()>)> {
+    let mut command = downloader.command(url, from_start);
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::other(DownloadError::new(
+                DownloadErrorKind::Failed,
+                format!("{} not found", command.get_program().to_string_lossy()),
+            )),
+            _ => e,
+        })?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "invalid stdout stream"))?;
+
+    *error.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "invalid stderr stream"))?;
+    let stderr = watch_stderr(stderr, Arc::clone(error), logger.clone());
+    handle.replace(child)?;
+
+    let (tx, rx) = mpsc::sync_channel(QUEUE_SIZE);
+    let queue = queue.clone();
+    queue.reset();
+    thread::spawn(move || loop {
+        let mut buf = vec![0; CHUNK_SIZE];
+        match stdout.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => {
+                buf.truncate(len);
+                queue.push();
+                if tx.send(buf).is_err() {
+                    queue.pop();
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok((rx, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_secs() {
+        assert_eq!(backoff_secs(0, 30), 1);
+        assert_eq!(backoff_secs(3, 30), 8);
+        assert_eq!(backoff_secs(5, 30), 30);
+        assert_eq!(backoff_secs(100, 30), 30);
+    }
+
+    /// reader of a downloader which already exited
+    fn ended() -> Reconnect {
+        Reconnect {
+            url: String::new(),
+            downloader: DownloaderConfig::default(),
+            config: ReconnectConfig::default(),
+            child: ChildHandle::default(),
+            rx: Mutex::new(mpsc::sync_channel(0).1),
+            chunk: vec![],
+            pos: 0,
+            retries: 0,
+            received: false,
+            error: LastError::default(),
+            stderr: None,
+            queue: InputQueue::default(),
+            lost: LostTime::default(),
+            outage: None,
+            logger: Log::quiet(),
+        }
+    }
+
+    #[test]
+    fn test_end() {
+        let mut reader = ended();
+        assert_eq!(reader.end().unwrap(), 0);
+
+        // stalls used up the retries without an error from the downloader
+        reader.retries = reader.config.max_retries;
+        let err = reader.end().unwrap_err();
+        assert!(err.to_string().contains("gave up after 5 restarts"));
+        reader.retries = 0;
+
+        // a failure before any data is an error
+        *reader.error.lock().unwrap() = Some(DownloadError::new(
+            DownloadErrorKind::Failed,
+            "ERROR: Unable to download webpage",
+        ));
+        assert!(reader.end().is_err());
+
+        // after data the stream just ends, until the retries run out
+        reader.received = true;
+        assert_eq!(reader.end().unwrap(), 0);
+        reader.retries = reader.config.max_retries;
+        assert!(reader.end().is_err());
+
+        // a closed input ends quietly
+        reader.child.close().unwrap();
+        assert_eq!(reader.end().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_stall_of_ended_stream() {
+        let mut reader = ended();
+        // no stream info, and no data before it stalled
+        reader.downloader.path = Some("/nonexistent/yt-dlp".to_string());
+        reader.config.stall_timeout_secs = 0;
+        let (_tx, rx) = mpsc::sync_channel(0);
+        reader.rx = Mutex::new(rx);
+
+        // not live anymore, the stream ends without a restart
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+        assert_eq!(reader.retries, 0);
+        assert!(reader.outage.is_none());
+    }
+
+    #[test]
+    fn test_lost_time() {
+        let lost = LostTime::default();
+        lost.clone().add(Duration::from_secs(3));
+        lost.add(Duration::from_millis(500));
+
+        assert_eq!(lost.take(), Duration::from_millis(3500));
+        assert_eq!(lost.take(), Duration::ZERO);
+    }
+}
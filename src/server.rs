@@ -0,0 +1,296 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{util::Log, Transcript};
+
+/// lines queued for a client before it is considered too slow and lines are dropped
+const CLIENT_QUEUE: usize = 64;
+/// open connections served at once, later ones are refused until one closes
+const MAX_CONNECTIONS: usize = 64;
+/// time a client has to send its request or to take a line
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// time a refused client has to take its response, the accept loop waits for it
+const REFUSE_TIMEOUT: Duration = Duration::from_millis(200);
+/// comment sent to idle clients so proxies and browsers keep the connection
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// caption overlay page for OBS browser sources and dashboards
+const OVERLAY: &str = include_str!("overlay.html");
+
+type Clients = Arc<Mutex<Vec<SyncSender<Arc<String>>>>>;
+
+/// local http server pushing every transcribed line as json through server-sent events.
+///
+/// - `GET /` caption overlay page
+/// - `GET /events` event stream of the lines
+pub struct Server {
+    addr: SocketAddr,
+    clients: Clients,
+    /// id of the next event
+    id: Mutex<u64>,
+}
+
+impl Server {
+    /// listen on `addr` and serve clients on background threads
+    pub fn bind<A: ToSocketAddrs>(addr: A, logger: Log) -> io::Result<Server> {
+        Server::listen(addr, MAX_CONNECTIONS, logger)
+    }
+
+    fn listen<A: ToSocketAddrs>(addr: A, max: usize, logger: Log) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Clients::default();
+        let open = Arc::new(AtomicUsize::new(0));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                if open.load(Ordering::Relaxed) >= max {
+                    logger.warn("too many server clients, refused a connection");
+                    // a client which doesn't read can't hold up the accept loop
+                    let _ = stream.set_write_timeout(Some(REFUSE_TIMEOUT));
+                    let _ = respond(
+                        &mut stream,
+                        "503 Service Unavailable",
+                        "text/plain",
+                        "too many clients",
+                    );
+                    continue;
+                }
+
+                let connection = Connection::new(&open);
+                let clients = accepted.clone();
+                let logger = logger.clone();
+                thread::spawn(move || {
+                    let _connection = connection;
+                    if let Err(e) = handle(stream, clients) {
+                        logger.verbose(format!("client disconnected: {}", e));
+                    }
+                });
+            }
+        });
+
+        Ok(Server {
+            addr,
+            clients,
+            id: Mutex::new(0),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// send a line to every connected client, slow clients miss it instead of blocking the caller
+    pub fn broadcast(&self, transcript: &Transcript) {
+        let Ok(json) = serde_json::to_string(transcript) else {
+            return;
+        };
+
+        let id = {
+            let mut id = self.id.lock().unwrap_or_else(|e| e.into_inner());
+            *id += 1;
+            *id
+        };
+        let event = Arc::new(format!("id: {}\ndata: {}\n\n", id, json));
+
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|client| match client.try_send(event.clone()) {
+                Ok(_) | Err(mpsc::TrySendError::Full(_)) => true,
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+/// counts an open connection until its thread ends
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn new(open: &Arc<AtomicUsize>) -> Connection {
+        open.fetch_add(1, Ordering::Relaxed);
+        Connection(open.clone())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn handle(mut stream: TcpStream, clients: Clients) -> io::Result<()> {
+    // a client which stalls doesn't hold its thread and connection
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // the headers aren't needed
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or(path);
+
+    match (method, path) {
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", OVERLAY),
+        ("GET", "/events") => events(stream, clients),
+        ("GET", _) => respond(&mut stream, "404 Not Found", "text/plain", "not found"),
+        _ => respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed",
+        ),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// keep the connection open and write the broadcast lines to it
+fn events(mut stream: TcpStream, clients: Clients) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: keep-alive\r\n\r\n"
+    )?;
+    stream.flush()?;
+
+    let (tx, rx) = mpsc::sync_channel(CLIENT_QUEUE);
+    clients.lock().unwrap_or_else(|e| e.into_inner()).push(tx);
+
+    loop {
+        match rx.recv_timeout(KEEP_ALIVE) {
+            Ok(event) => stream.write_all(event.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, time::Instant};
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        stream
+    }
+
+    #[test]
+    fn test_server() {
+        let server = Server::bind("127.0.0.1:0", Log::default()).unwrap();
+        let addr = server.local_addr();
+
+        let mut page = String::new();
+        get(addr, "/").read_to_string(&mut page).unwrap();
+        assert!(page.starts_with("HTTP/1.1 200 OK"));
+        assert!(page.contains("EventSource"));
+
+        let mut missing = String::new();
+        get(addr, "/missing").read_to_string(&mut missing).unwrap();
+        assert!(missing.starts_with("HTTP/1.1 404"));
+
+        let events = get(addr, "/events");
+        events
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(events);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+
+        // the client is registered once its headers are written
+        while server.clients.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        server.broadcast(&Transcript {
+            stream: Some("a".to_string()),
+            ..Transcript::test("hello", 1000, 2000)
+        });
+
+        let mut id = String::new();
+        reader.read_line(&mut id).unwrap();
+        assert_eq!(id, "id: 1\n");
+
+        let mut data = String::new();
+        reader.read_line(&mut data).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(json["text"], "hello");
+        assert_eq!(json["stream"], "a");
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let server = Server::listen("127.0.0.1:0", 1, Log::quiet()).unwrap();
+        let addr = server.local_addr();
+
+        let events = get(addr, "/events");
+        while server.clients.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // refused right away, before the request is read
+        let mut refused = String::new();
+        TcpStream::connect(addr)
+            .unwrap()
+            .read_to_string(&mut refused)
+            .unwrap();
+        assert!(refused.starts_with("HTTP/1.1 503"));
+
+        // the closed client frees its connection once the next line fails to reach it
+        drop(events);
+        let mut page = String::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !page.starts_with("HTTP/1.1 200") {
+            assert!(
+                Instant::now() < deadline,
+                "the closed client never freed its connection"
+            );
+            server.broadcast(&Transcript::test("", 0, 0));
+            thread::sleep(Duration::from_millis(10));
+
+            // still refused while the old connection is open
+            page.clear();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let _ = write!(stream, "GET / HTTP/1.1\r\n\r\n");
+            let _ = stream.read_to_string(&mut page);
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    downloader::{DownloadError, DownloadErrorKind, DownloaderConfig},
+    reconnect::{InputQueue, LostTime, Reconnect, ReconnectConfig},
+    ts::{PACKET_SIZE, SYNC_BYTE},
+    util::Log,
+};
+
+type BoxedReader = Box<dyn Read + Send + Sync>;
+type SourceReader = BufReader<BoxedReader>;
+
+/// where the audio stream comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// youtube url or video id streamed by yt-dlp
+    YtDlp(String),
+    /// local audio file
+    File(PathBuf),
+    /// audio piped to stdin
+    Stdin,
+    /// shell command writing audio to its stdout
+    Command(String),
+}
+
+/// container of the incoming byte stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Container {
+    /// mpeg-ts, demuxed as a stream
+    Ts,
+    /// anything symphonia can probe, with an optional file extension hint
+    Other(Option<String>),
+}
+
+/// stream metadata reported by yt-dlp
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// `is_live`, `is_upcoming`, `was_live`, `not_live` or `post_live`
+    pub live_status: Option<String>,
+    /// unix time in seconds the broadcast started or is scheduled to start
+    pub release_timestamp: Option<i64>,
+    /// unix time in seconds the video was uploaded
+    pub timestamp: Option<i64>,
+}
+
+impl StreamInfo {
+    /// ask yt-dlp for the metadata of `url` without downloading it
+    pub fn fetch(url: &str, downloader: &DownloaderConfig) -> io::Result<StreamInfo> {
+        let output = downloader
+            .info_command(url)
+            // upcoming streams have no formats but still have metadata
+            .args([
+                "--skip-download",
+                "--no-warnings",
+                "--ignore-no-formats-error",
+            ])
+            .args([
+                "--print",
+                "%(live_status)s %(release_timestamp)s %(timestamp)s",
+            ])
+            .stderr(Stdio::piped())
+            .output()?;
+
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .rev()
+                .find_map(DownloadError::parse)
+                .unwrap_or_else(|| {
+                    DownloadError::new(
+                        DownloadErrorKind::Failed,
+                        "failed to fetch stream info from yt-dlp",
+                    )
+                });
+            return Err(io::Error::other(error));
+        }
+
+        Ok(StreamInfo::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// parse the `--print` line of `fetch`, yt-dlp prints `NA` for missing fields
+    fn parse(line: &str) -> StreamInfo {
+        let mut fields = line
+            .split_whitespace()
+            .map(|field| Some(field).filter(|field| *field != "NA"));
+
+        let live_status = fields.next().flatten().map(str::to_string);
+        let release_timestamp = fields.next().flatten().and_then(|t| t.parse().ok());
+        let timestamp = fields.next().flatten().and_then(|t| t.parse().ok());
+
+        StreamInfo {
+            live_status,
+            release_timestamp,
+            timestamp,
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live_status.as_deref() == Some("is_live")
+    }
+
+    /// scheduled live stream or premiere which has not started yet
+    pub fn is_upcoming(&self) -> bool {
+        self.live_status.as_deref() == Some("is_upcoming")
+    }
+
+    /// video or past stream, which is downloaded as fast as it's read
+    pub fn is_recorded(&self) -> bool {
+        matches!(
+            self.live_status.as_deref(),
+            Some("not_live" | "was_live" | "post_live")
+        )
+    }
+
+    /// unix time in seconds the stream started
+    pub fn start_time(&self) -> Option<i64> {
+        self.release_timestamp.or(self.timestamp)
+    }
+}
+
+pub struct Input {
+    pub reader: SourceReader,
+    pub container: Container,
+    child: ChildHandle,
+    /// downloader chunks read ahead of the decoder
+    queue: Option<InputQueue>,
+    /// stream time lost by restarts of the downloader
+    lost: Option<LostTime>,
+}
+
+/// process feeding an input, shared with readers which restart it
+#[derive(Clone, Default)]
+pub struct ChildHandle {
+    child: Arc<Mutex<Option<Child>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ChildHandle {
+    /// swap in a restarted process, which is stopped right away if the input is closed
+    pub fn replace(&self, mut child: Child) -> io::Result<()> {
+        if self.is_closed() {
+            child.kill()?;
+            child.wait()?;
+            return Ok(());
+        }
+
+        *self.child.lock().unwrap_or_else(|e| e.into_inner()) = Some(child);
+        Ok(())
+    }
+
+    /// stop the current process
+    pub fn kill(&self) -> io::Result<()> {
+        let child = self.child.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(mut child) = child {
+            child.kill()?;
+            child.wait()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// stop the process for good, it isn't restarted anymore
+    pub fn close(&self) -> io::Result<()> {
+        self.closed.store(true, Ordering::Relaxed);
+        self.kill()
+    }
+}
+
+impl Source {
+    /// `-` is stdin, `command` forces a shell command,
+    /// an existing path is a local file and anything else is passed to yt-dlp
+    pub fn parse(input: &str, command: bool) -> Source {
+        if command {
+            Source::Command(input.to_string())
+        } else if input == "-" {
+            Source::Stdin
+        } else if Path::new(input).is_file() {
+            Source::File(PathBuf::from(input))
+        } else {
+            Source::YtDlp(input.to_string())
+        }
+    }
+
+    /// short name of the source labeling its lines when several streams are transcribed,
+    /// the video id of youtube urls or the file name of local files
+    pub fn label(&self) -> String {
+        match self {
+            Source::YtDlp(url) => {
+                let url = url.split('#').next().unwrap_or(url);
+                let (path, query) = url.split_once('?').unwrap_or((url, ""));
+                query
+                    .split('&')
+                    .find_map(|param| param.strip_prefix("v="))
+                    .or_else(|| path.rsplit('/').find(|part| !part.is_empty()))
+                    .unwrap_or(url)
+                    .to_string()
+            }
+            Source::File(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+            Source::Stdin => "stdin".to_string(),
+            Source::Command(cmd) => cmd.split_whitespace().next().unwrap_or(cmd).to_string(),
+        }
+    }
+
+    /// live sources can't wait for a slow consumer. urls are looked up with `downloader`,
+    /// they count as live unless yt-dlp tells they are recorded
+    pub fn is_live(&self, downloader: &DownloaderConfig) -> bool {
+        match self {
+            Source::YtDlp(url) => {
+                !StreamInfo::fetch(url, downloader).is_ok_and(|info| info.is_recorded())
+            }
+            _ => false,
+        }
+    }
+
+    pub fn open(&self) -> io::Result<Input> {
+        self.open_with(
+            &DownloaderConfig::default(),
+            &ReconnectConfig::default(),
+            Log::default(),
+        )
+    }
+
+    /// open the source, urls are streamed by `downloader`
+    /// and a dropped stream is restarted following `reconnect`
+    pub fn open_with(
+        &self,
+        downloader: &DownloaderConfig,
+        reconnect: &ReconnectConfig,
+        logger: Log,
+    ) -> io::Result<Input> {
+        let handle = ChildHandle::default();
+
+        let mut queue = None;
+        let mut lost = None;
+        let (reader, hint): (BoxedReader, Option<String>) = match self {
+            Source::YtDlp(url) => {
+                let reader = Reconnect::new(
+                    url,
+                    downloader.clone(),
+                    reconnect.clone(),
+                    handle.clone(),
+                    logger,
+                )?;
+                queue = Some(reader.queue());
+                lost = Some(reader.lost_time());
+                (Box::new(reader) as BoxedReader, None)
+            }
+            Source::File(path) => {
+                let hint = path
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase());
+                (Box::new(File::open(path)?) as BoxedReader, hint)
+            }
+            Source::Stdin => (Box::new(io::stdin()) as BoxedReader, None),
+            Source::Command(cmd) => {
+                let mut child = shell(cmd).stdout(Stdio::piped()).spawn()?;
+                let stdout = take_stdout(&mut child)?;
+                handle.replace(child)?;
+                (Box::new(stdout) as BoxedReader, None)
+            }
+        };
+
+        let mut reader = BufReader::new(reader);
+        let container = if is_mpeg_ts(reader.fill_buf()?) {
+            Container::Ts
+        } else {
+            Container::Other(hint)
+        };
+
+        Ok(Input {
+            reader,
+            container,
+            child: handle,
+            queue,
+            lost,
+        })
+    }
+}
+
+impl Input {
+    /// input of any byte source like a socket or an in-memory buffer,
+    /// `container` tells how it's decoded since it isn't probed
+    pub fn from_reader<R>(reader: R, container: Container) -> Input
+    where
+        R: Read + Send + Sync + 'static,
+    {
+        Input {
+            reader: BufReader::new(Box::new(reader)),
+            container,
+            child: ChildHandle::default(),
+            queue: None,
+            lost: None,
+        }
+    }
+
+    /// handle to close the input from another thread
+    pub fn handle(&self) -> ChildHandle {
+        self.child.clone()
+    }
+
+    /// read ahead queue of downloaded streams
+    pub fn queue(&self) -> Option<InputQueue> {
+        self.queue.clone()
+    }
+
+    /// stream time lost by restarts of downloaded streams
+    pub fn lost_time(&self) -> Option<LostTime> {
+        self.lost.clone()
+    }
+
+    /// move the reader out for decoders that need to own it
+    pub fn take_reader(&mut self) -> SourceReader {
+        std::mem::replace(&mut self.reader, BufReader::new(Box::new(io::empty())))
+    }
+
+    /// stop the child process feeding this input if there is one
+    pub fn close(&mut self) -> io::Result<()> {
+        self.child.close()
+    }
+}
+
+fn take_stdout(child: &mut Child) -> io::Result<std::process::ChildStdout> {
+    child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "invalid stdout stream"))
+}
+
+#[cfg(windows)]
+fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.args(["/C", cmd]);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.args(["-c", cmd]);
+    command
+}
+
+/// check sync bytes of the first two ts packets
+pub fn is_mpeg_ts(buf: &[u8]) -> bool {
+    match buf.len() {
+        0 => false,
+        len if len <= PACKET_SIZE => buf[0] == SYNC_BYTE,
+        _ => buf[0] == SYNC_BYTE && buf[PACKET_SIZE] == SYNC_BYTE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(Source::parse("-", false), Source::Stdin);
+        assert_eq!(
+            Source::parse("tests/test.ts", false),
+            Source::File(PathBuf::from("tests/test.ts"))
+        );
+        assert_eq!(
+            Source::parse("jfKfPfyJRdk", false),
+            Source::YtDlp("jfKfPfyJRdk".to_string())
+        );
+        assert_eq!(
+            Source::parse("ffmpeg -i a.mp3 -f wav -", true),
+            Source::Command("ffmpeg -i a.mp3 -f wav -".to_string())
+        );
+    }
+
+    #[test]
+    fn test_source_label() {
+        let label = |input| Source::parse(input, false).label();
+
+        assert_eq!(label("jfKfPfyJRdk"), "jfKfPfyJRdk");
+        assert_eq!(
+            label("https://www.youtube.com/watch?v=jfKfPfyJRdk&t=10"),
+            "jfKfPfyJRdk"
+        );
+        assert_eq!(label("https://youtu.be/jfKfPfyJRdk?si=abc"), "jfKfPfyJRdk");
+        assert_eq!(label("https://www.twitch.tv/channel/"), "channel");
+        assert_eq!(label("tests/test.ts"), "test.ts");
+        assert_eq!(label("-"), "stdin");
+    }
+
+    #[test]
+    fn test_parse_stream_info() {
+        let info = StreamInfo::parse("is_live 1684108800 NA\n");
+        assert!(info.is_live());
+        assert_eq!(info.start_time(), Some(1684108800));
+
+        let info = StreamInfo::parse("was_live 1684108800 NA\n");
+        assert!(!info.is_live());
+        assert!(info.is_recorded());
+
+        let info = StreamInfo::parse("NA NA 1684108800\n");
+        assert!(!info.is_live());
+        assert!(!info.is_recorded());
+        assert_eq!(info.live_status, None);
+        assert_eq!(info.start_time(), Some(1684108800));
+    }
+
+    #[test]
+    fn test_open_ts_file() {
+        let input = Source::File(PathBuf::from("tests/test.ts")).open().unwrap();
+        assert_eq!(input.container, Container::Ts);
+    }
+}
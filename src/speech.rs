@@ -1,9 +1,77 @@
+use serde::{Deserialize, Serialize};
 use std::os::raw::c_int;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperState};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperError, WhisperState};
+
+/// what whisper does with the speech
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Task {
+    #[default]
+    Transcribe,
+    /// translate the speech to english
+    Translate,
+    /// transcribe and translate the same segment
+    Both,
+}
+
+/// whisper decoding parameters
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecodeConfig {
+    /// number of beams of beam search, greedy sampling is used below 2
+    pub beam_size: c_int,
+    /// number of candidates of greedy sampling
+    pub best_of: c_int,
+    /// initial sampling temperature
+    pub temperature: f32,
+    /// temperature increase when decoding fails the thresholds
+    pub temperature_inc: f32,
+    /// decoding with a higher compression entropy is retried
+    pub entropy_thold: f32,
+    /// decoding with a lower average log probability is retried
+    pub logprob_thold: f32,
+    /// text given to whisper before every segment, like names and vocabulary of a channel
+    pub initial_prompt: Option<String>,
+    /// give the text of the previous segment to whisper as context
+    pub carry_context: bool,
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        DecodeConfig {
+            beam_size: 0,
+            best_of: 1,
+            temperature: 0.0,
+            temperature_inc: 0.2,
+            entropy_thold: 2.4,
+            logprob_thold: -1.0,
+            initial_prompt: None,
+            carry_context: false,
+        }
+    }
+}
+
+impl DecodeConfig {
+    /// cheapest decoding of these settings for catching up with a live stream,
+    /// greedy without temperature retries or carried context
+    pub fn fast(&self) -> DecodeConfig {
+        DecodeConfig {
+            beam_size: 0,
+            best_of: 1,
+            temperature_inc: 0.0,
+            carry_context: false,
+            ..self.clone()
+        }
+    }
+}
 
 pub struct SpeechConfig<'a> {
     pub threads: c_int,
     pub lang: Option<&'a str>,
+    /// use whisper translate task to english
+    pub translate: bool,
+    pub decode: DecodeConfig,
+    /// text given to whisper before the audio
+    pub prompt: Option<&'a str>,
 }
 
 impl<'a> Default for SpeechConfig<'a> {
@@ -11,13 +79,35 @@ impl<'a> Default for SpeechConfig<'a> {
         SpeechConfig {
             threads: 4,
             lang: Some("en"),
+            translate: false,
+            decode: DecodeConfig::default(),
+            prompt: None,
         }
     }
 }
 
 impl<'a> SpeechConfig<'a> {
     pub fn new(threads: c_int, lang: Option<&'a str>) -> SpeechConfig<'a> {
-        SpeechConfig { threads, lang }
+        SpeechConfig {
+            threads,
+            lang,
+            ..Default::default()
+        }
+    }
+
+    pub fn translate(mut self, translate: bool) -> SpeechConfig<'a> {
+        self.translate = translate;
+        self
+    }
+
+    pub fn decode(mut self, decode: DecodeConfig) -> SpeechConfig<'a> {
+        self.decode = decode;
+        self
+    }
+
+    pub fn prompt(mut self, prompt: Option<&'a str>) -> SpeechConfig<'a> {
+        self.prompt = prompt;
+        self
     }
 }
 
@@ -35,48 +125,125 @@ impl<'a> WhisperPayload<'a> {
     }
 }
 
-pub fn process<F: FnMut(&str, i64)>(
+/// text piece decoded by whisper
+#[derive(Serialize, Clone, Debug)]
+pub struct Token {
+    pub text: String,
+    /// probability of this token
+    pub p: f32,
+}
+
+/// whisper output segment, times are in ms relative to the audio data
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub text: String,
+    pub start: i64,
+    pub end: i64,
+    pub tokens: Vec<Token>,
+}
+
+/// language code detected by whisper with its probability
+pub fn detect_language(
+    state: &mut WhisperState<'_>,
+    audio_data: &[f32],
+    threads: c_int,
+) -> Option<(&'static str, f32)> {
+    state.pcm_to_mel(audio_data, threads as usize).ok()?;
+    let probs = state.lang_detect(0, threads as usize).ok()?;
+
+    let (id, p) = probs
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+    Some((whisper_rs::get_lang_str(id as i32)?, *p))
+}
+
+pub fn process<F: FnMut(&Segment)>(
     state: &mut WhisperState<'_>,
     payload: &mut WhisperPayload,
     f: &mut F,
-) {
+) -> Result<(), WhisperError> {
     let WhisperPayload { audio_data, config } = payload;
 
     let params = get_params(config);
 
-    state.full(params, audio_data).expect("failed to run model");
+    state.full(params, audio_data)?;
 
     // fetch the results
-    let num_segments = state
-        .full_n_segments()
-        .expect("failed to get number of segments");
+    let num_segments = state.full_n_segments()?;
 
     let mut last_segment = String::from("");
     for i in 0..num_segments {
-        if let (Ok(segment), Ok(start_timestamp)) =
-            (state.full_get_segment_text(i), state.full_get_segment_t0(i))
-        {
+        if let (Ok(segment), Ok(start_timestamp), Ok(end_timestamp)) = (
+            state.full_get_segment_text(i),
+            state.full_get_segment_t0(i),
+            state.full_get_segment_t1(i),
+        ) {
             if last_segment != segment {
-                let segment = process_segment(segment.as_ref());
-                if !segment.is_empty() {
-                    f(&segment, start_timestamp);
+                let text = process_segment(segment.as_ref());
+                if !text.is_empty() {
+                    f(&Segment {
+                        text,
+                        // whisper timestamps are in 10ms units
+                        start: start_timestamp * 10,
+                        end: end_timestamp * 10,
+                        tokens: get_tokens(state, i),
+                    });
                 }
             }
 
             last_segment = segment;
         }
     }
+
+    Ok(())
+}
+
+fn get_tokens(state: &WhisperState<'_>, segment: c_int) -> Vec<Token> {
+    let num_tokens = state.full_n_tokens(segment).unwrap_or(0);
+
+    (0..num_tokens)
+        .filter_map(|i| {
+            let text = state.full_get_token_text(segment, i).ok()?;
+            let p = state.full_get_token_prob(segment, i).ok()?;
+            Some(Token { text, p })
+        })
+        // skip special tokens like [_BEG_] or <|endoftext|>
+        .filter(|token| !token.text.starts_with("[_") && !token.text.starts_with("<|"))
+        .collect()
 }
 
 fn get_params<'a, 'b>(config: &SpeechConfig<'a>) -> FullParams<'a, 'b> {
-    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    let decode = &config.decode;
+    let strategy = if decode.beam_size > 1 {
+        SamplingStrategy::BeamSearch {
+            beam_size: decode.beam_size,
+            patience: -1.0,
+        }
+    } else {
+        SamplingStrategy::Greedy {
+            best_of: decode.best_of,
+        }
+    };
+
+    let mut params = FullParams::new(strategy);
     params.set_n_threads(config.threads);
     params.set_language(config.lang);
+    params.set_translate(config.translate);
     params.set_suppress_blank(true);
     params.set_no_speech_thold(1.);
     params.set_single_segment(true);
     params.set_no_context(true);
 
+    params.set_temperature(decode.temperature);
+    params.set_temperature_inc(decode.temperature_inc);
+    params.set_entropy_thold(decode.entropy_thold);
+    params.set_logprob_thold(decode.logprob_thold);
+    if let Some(prompt) = config.prompt {
+        params.set_initial_prompt(prompt);
+    }
+
     // disable anything that prints to stdout
     params.set_print_special(false);
     params.set_print_progress(false);
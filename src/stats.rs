@@ -0,0 +1,181 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Mutex,
+};
+
+use crate::vad::{self, VadSegment};
+
+/// fill level of a queue between pipeline threads
+#[derive(Default, Debug)]
+pub struct Level {
+    len: AtomicUsize,
+    capacity: AtomicUsize,
+}
+
+impl Level {
+    pub fn set(&self, len: usize, capacity: usize) {
+        self.len.store(len, Ordering::Relaxed);
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// queued items and the capacity of the queue
+    pub fn get(&self) -> (usize, usize) {
+        (
+            self.len.load(Ordering::Relaxed),
+            self.capacity.load(Ordering::Relaxed),
+        )
+    }
+
+    /// fill level from 0 to 1, 0 before the queue is known
+    pub fn ratio(&self) -> f64 {
+        match self.get() {
+            (_, 0) => 0.0,
+            (len, capacity) => (len as f64 / capacity as f64).min(1.0),
+        }
+    }
+}
+
+/// state of a running stream updated by its pipeline threads, read by the terminal ui
+#[derive(Default, Debug)]
+pub struct Stats {
+    /// downloader chunks waiting to be demuxed
+    pub input: Level,
+    /// pcm samples waiting for vad
+    pub pcm: Level,
+    /// speech segments waiting for whisper
+    pub segments: Level,
+    /// ms whisper took for the last segment
+    whisper_ms: AtomicU64,
+    /// language of the last segment
+    language: Mutex<Option<String>>,
+    /// bits of the f64 real time factor of the last segment
+    rtf: AtomicU64,
+    /// ms from the last segment leaving vad until its lines were out
+    lag_ms: AtomicU64,
+    catching_up: AtomicBool,
+    /// ms of pcm dropped before vad
+    dropped_audio_ms: AtomicU64,
+    /// speech segments dropped before whisper
+    dropped_segments: AtomicU64,
+    /// ms of the dropped speech segments
+    dropped_speech_ms: AtomicU64,
+}
+
+impl Stats {
+    pub fn whisper_ms(&self) -> u64 {
+        self.whisper_ms.load(Ordering::Relaxed)
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.language
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// whisper time of the last segment divided by its duration, above 1 is slower than real time
+    pub fn rtf(&self) -> f64 {
+        f64::from_bits(self.rtf.load(Ordering::Relaxed))
+    }
+
+    pub fn lag_ms(&self) -> u64 {
+        self.lag_ms.load(Ordering::Relaxed)
+    }
+
+    /// whether the stream lags too far behind and whisper is catching up
+    pub fn catching_up(&self) -> bool {
+        self.catching_up.load(Ordering::Relaxed)
+    }
+
+    /// seconds of pcm dropped because vad couldn't keep up
+    pub fn dropped_audio_secs(&self) -> f64 {
+        self.dropped_audio_ms.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// speech segments dropped because whisper couldn't keep up
+    pub fn dropped_segments(&self) -> u64 {
+        self.dropped_segments.load(Ordering::Relaxed)
+    }
+
+    /// seconds of the dropped speech segments
+    pub fn dropped_speech_secs(&self) -> f64 {
+        self.dropped_speech_ms.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// total audio the pipeline lost, like `dropped 3.0s audio, 2 segments (4.5s speech)`
+    pub fn drop_summary(&self) -> String {
+        format!(
+            "dropped {:.1}s audio, {} segments ({:.1}s speech)",
+            self.dropped_audio_secs(),
+            self.dropped_segments(),
+            self.dropped_speech_secs()
+        )
+    }
+
+    pub(crate) fn drop_audio(&self, samples: usize, sample_rate: u32) {
+        if samples > 0 && sample_rate > 0 {
+            let ms = samples as u64 * 1000 / sample_rate as u64;
+            self.dropped_audio_ms.fetch_add(ms, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn drop_segments<'a, I>(&self, segments: I)
+    where
+        I: IntoIterator<Item = &'a VadSegment>,
+    {
+        let (count, samples) = segments.into_iter().fold((0, 0), |(count, samples), s| {
+            (count + 1, samples + s.data.len() as u64)
+        });
+        self.dropped_segments.fetch_add(count, Ordering::Relaxed);
+        self.dropped_speech_ms
+            .fetch_add(samples * 1000 / vad::SAMPLE_RATE as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_whisper(&self, ms: u64, language: &str) {
+        self.whisper_ms.store(ms, Ordering::Relaxed);
+        *self.language.lock().unwrap_or_else(|e| e.into_inner()) = Some(language.to_string());
+    }
+
+    pub(crate) fn set_lag(&self, rtf: f64, lag_ms: u64, catching_up: bool) {
+        self.rtf.store(rtf.to_bits(), Ordering::Relaxed);
+        self.lag_ms.store(lag_ms, Ordering::Relaxed);
+        self.catching_up.store(catching_up, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_ratio() {
+        let level = Level::default();
+        assert_eq!(level.ratio(), 0.0);
+
+        level.set(5, 20);
+        assert_eq!(level.get(), (5, 20));
+        assert_eq!(level.ratio(), 0.25);
+
+        level.set(30, 20);
+        assert_eq!(level.ratio(), 1.0);
+    }
+
+    #[test]
+    fn test_drops() {
+        let stats = Stats::default();
+        stats.drop_audio(24000, 48000);
+        stats.drop_audio(16000, 16000);
+        stats.drop_segments(&[VadSegment {
+            data: vec![0.0; 8000],
+            ..Default::default()
+        }]);
+
+        assert_eq!(stats.dropped_audio_secs(), 1.5);
+        assert_eq!(stats.dropped_segments(), 1);
+        assert_eq!(stats.dropped_speech_secs(), 0.5);
+        assert_eq!(
+            stats.drop_summary(),
+            "dropped 1.5s audio, 1 segments (0.5s speech)"
+        );
+    }
+}
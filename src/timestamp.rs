@@ -0,0 +1,98 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, TimeZone, Utc};
+use clap::ValueEnum;
+
+use crate::{source::StreamInfo, util};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampMode {
+    /// time since the transcription started
+    #[default]
+    Offset,
+    /// time since the stream started
+    Stream,
+    /// wall clock in local time
+    Local,
+    /// wall clock in UTC
+    Utc,
+}
+
+/// turns ms from the transcription start into displayed timestamps
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    mode: TimestampMode,
+    /// ms between the stream start and the transcription start
+    stream_offset: i64,
+    /// unix time in ms of the transcription start
+    epoch: i64,
+}
+
+impl Clock {
+    /// `info` is the yt-dlp metadata of the stream if there is one.
+    /// live streams are joined at the live edge unless `from_start`, so the transcription starts now,
+    /// while archived streams start at the time the broadcast started
+    pub fn new(mode: TimestampMode, info: Option<&StreamInfo>, from_start: bool) -> Clock {
+        let now = now_ms();
+        let start = info.and_then(|info| info.start_time()).map(|s| s * 1000);
+        let live = info.map(|info| info.is_live()).unwrap_or(false) && !from_start;
+
+        let (stream_offset, epoch) = match start {
+            Some(start) if live => (now - start, now),
+            Some(start) => (0, start),
+            None => (0, now),
+        };
+
+        Clock {
+            mode,
+            stream_offset,
+            epoch,
+        }
+    }
+
+    pub fn format(&self, ms: i64) -> String {
+        match self.mode {
+            TimestampMode::Offset => util::format_timestamp_to_time(ms),
+            TimestampMode::Stream => util::format_timestamp_to_time(self.stream_offset + ms),
+            TimestampMode::Local => Local
+                .timestamp_millis_opt(self.epoch + ms)
+                .single()
+                .map(|t| t.format("%H:%M:%S%.3f").to_string())
+                .unwrap_or_default(),
+            TimestampMode::Utc => Utc
+                .timestamp_millis_opt(self.epoch + ms)
+                .single()
+                .map(|t| t.format("%H:%M:%S%.3fZ").to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_format() {
+        let clock = Clock {
+            mode: TimestampMode::Stream,
+            stream_offset: 1000 * 60 * 60 * 2,
+            epoch: 0,
+        };
+        assert_eq!(clock.format(1500), "02:00:01.500");
+
+        let clock = Clock {
+            mode: TimestampMode::Utc,
+            stream_offset: 0,
+            epoch: 1000 * 60 * 60 * 25,
+        };
+        assert_eq!(clock.format(1500), "01:00:01.500Z");
+    }
+}
@@ -0,0 +1,1391 @@
+use std::{
+    ffi::c_int,
+    io::BufRead,
+    mem::MaybeUninit,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ringbuf::{LocalRb, Rb};
+use serde::Serialize;
+use whisper_rs::{WhisperContext, WhisperError, WhisperState};
+
+use crate::{
+    audio::{self, PcmBatcher, StreamResampler},
+    latency::{self, CatchUp, LagMonitor, LatencyConfig},
+    queue::{BoundedQueue, Closed, OverflowPolicy, Weight},
+    reconnect::LostTime,
+    source::{Container, Input},
+    speech::{self, DecodeConfig, SpeechConfig, Task, Token, WhisperPayload},
+    stats::Stats,
+    ts::TsDemuxer,
+    util::Log,
+    vad::{self, split_audio_data_with_window_size, VadConfig, VadState, WINDOW_SIZE_SAMPLES},
+    Error, Result, Stage,
+};
+
+type PcmQueue = Arc<BoundedQueue<Pcm>>;
+type SegmentQueue = Arc<BoundedQueue<QueuedSegment>>;
+
+/// highest common stream sample rate, sizes the pcm buffer
+const MAX_SAMPLE_RATE: usize = 48000;
+/// least time between two reports of dropped audio
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// language of lines decoded in `auto` mode before any language was detected
+const UNDETERMINED_LANGUAGE: &str = "und";
+
+/// messages of the stages to the whisper thread
+enum WorkerMessage {
+    /// a stream joined the worker
+    Add(StreamSlot),
+    /// a vad thread pushed segments or closed its queue
+    Ready,
+}
+
+/// mono pcm pushed by a session
+struct PcmChunk {
+    data: Vec<f32>,
+    sample_rate: u32,
+}
+
+/// pcm queue item, audio or the marker of audio lost in between
+enum Pcm {
+    Audio(PcmChunk),
+    /// lost samples at the vad rate, the vad clock moves on by them
+    Gap(u64),
+}
+
+impl Pcm {
+    /// marker of dropped pcm, which may include earlier markers
+    fn gap(dropped: &[Pcm]) -> Pcm {
+        Pcm::Gap(dropped.iter().map(Pcm::vad_samples).sum())
+    }
+
+    /// length at the vad rate
+    fn vad_samples(&self) -> u64 {
+        match self {
+            Pcm::Audio(chunk) => (chunk.data.len() as u64 * vad::SAMPLE_RATE as u64)
+                .checked_div(chunk.sample_rate as u64)
+                .unwrap_or(0),
+            Pcm::Gap(samples) => *samples,
+        }
+    }
+}
+
+impl Weight for Pcm {
+    fn weight(&self) -> usize {
+        match self {
+            Pcm::Audio(chunk) => chunk.data.len(),
+            Pcm::Gap(_) => 0,
+        }
+    }
+
+    fn is_marker(&self) -> bool {
+        matches!(self, Pcm::Gap(_))
+    }
+}
+
+/// speech segment waiting for whisper
+struct QueuedSegment {
+    segment: vad::VadSegment,
+    /// time vad pushed the segment, the lag of the stream is measured from it
+    queued: Instant,
+}
+
+impl Weight for QueuedSegment {}
+
+/// segments of one stream waiting for whisper
+struct StreamSlot {
+    label: Option<String>,
+    segments: SegmentQueue,
+    /// text of the last segment for `carry_context`
+    previous_text: String,
+    /// language last detected in `auto` mode
+    language: Option<&'static str>,
+    stats: Arc<Stats>,
+    monitor: LagMonitor,
+    /// disconnected when whisper drops the stream after its last segment
+    _done: SyncSender<()>,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        // also stops the vad thread when the whisper thread stops early
+        self.segments.close();
+    }
+}
+
+/// one transcribed line, times are in ms from the stream start
+#[derive(Serialize, Clone, Debug)]
+pub struct Transcript {
+    pub start: i64,
+    pub end: i64,
+    pub text: String,
+    /// language of `text`, `und` when none could be detected
+    pub language: String,
+    /// probability of `language` when it is detected by whisper
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language_probability: Option<f32>,
+    /// id of the vad segment this line was transcribed from
+    pub segment: usize,
+    pub tokens: Vec<Token>,
+    /// english translation of `text` when transcribing and translating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+    /// label of the stream when several streams share a scheduler
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+#[cfg(test)]
+impl Transcript {
+    /// english line of `text` with the optional fields unset
+    pub(crate) fn test(text: &str, start: i64, end: i64) -> Transcript {
+        Transcript {
+            start,
+            end,
+            text: text.to_string(),
+            language: "en".to_string(),
+            language_probability: None,
+            segment: 0,
+            tokens: vec![],
+            translation: None,
+            stream: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    threads: c_int,
+    lang: String,
+    /// chinese variant to convert detected chinese to
+    #[cfg(feature = "zh")]
+    zh_variant: Option<String>,
+    task: Task,
+    vad: VadConfig,
+    decode: DecodeConfig,
+    logger: Log,
+    overflow: OverflowPolicy,
+    latency: LatencyConfig,
+}
+
+pub struct TranscriberBuilder {
+    model: Option<String>,
+    config: Config,
+}
+
+impl Default for TranscriberBuilder {
+    fn default() -> Self {
+        TranscriberBuilder {
+            model: None,
+            config: Config {
+                threads: 1,
+                lang: "en".to_string(),
+                #[cfg(feature = "zh")]
+                zh_variant: None,
+                task: Task::Transcribe,
+                vad: VadConfig::default(),
+                decode: DecodeConfig::default(),
+                logger: Log::default(),
+                overflow: OverflowPolicy::DropNewest,
+                latency: LatencyConfig::default(),
+            },
+        }
+    }
+}
+
+impl TranscriberBuilder {
+    /// path of whisper model
+    pub fn model<S: Into<String>>(mut self, path: S) -> Self {
+        self.model = Some(path.into());
+        self
+    }
+
+    /// usage thread number for whisper
+    pub fn threads(mut self, threads: u8) -> Self {
+        self.config.threads = threads as c_int;
+        self
+    }
+
+    /// whisper parse target language, `auto` detects the language of each segment
+    pub fn lang<S: Into<String>>(mut self, lang: S) -> Self {
+        self.config.lang = lang.into();
+        self
+    }
+
+    /// chinese variant like `zh-tw` used for chinese detected in `auto` language
+    #[cfg(feature = "zh")]
+    pub fn zh_variant<S: Into<String>>(mut self, variant: Option<S>) -> Self {
+        self.config.zh_variant = variant.map(Into::into);
+        self
+    }
+
+    /// transcribe, translate to english or both
+    pub fn task(mut self, task: Task) -> Self {
+        self.config.task = task;
+        self
+    }
+
+    /// speech detection parameters
+    pub fn vad(mut self, vad: VadConfig) -> Self {
+        self.config.vad = vad;
+        self
+    }
+
+    /// whisper decoding parameters
+    pub fn decode(mut self, decode: DecodeConfig) -> Self {
+        self.config.decode = decode;
+        self
+    }
+
+    /// show log of runtime
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.logger = Log::new(verbose);
+        self
+    }
+
+    /// log of runtime and warnings like dropped audio, `Log::quiet` shows nothing
+    pub fn logger(mut self, logger: Log) -> Self {
+        self.config.logger = logger;
+        self
+    }
+
+    /// wait for the pipeline instead of dropping audio when it can't keep up,
+    /// useful for local files which are read faster than real time.
+    /// same as `overflow(OverflowPolicy::Block)`, or `DropNewest` when false
+    pub fn blocking(mut self, blocking: bool) -> Self {
+        self.config.overflow = if blocking {
+            OverflowPolicy::Block
+        } else {
+            OverflowPolicy::DropNewest
+        };
+        self
+    }
+
+    /// what the full pcm and segment buffers do with new data when the pipeline can't keep up,
+    /// `DropNewest` by default for live streams, `Block` keeps all the audio of local inputs
+    pub fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.config.overflow = policy;
+        self
+    }
+
+    /// catch up with streams which lag too far behind real time
+    pub fn latency(mut self, latency: LatencyConfig) -> Self {
+        self.config.latency = latency;
+        self
+    }
+
+    /// load the whisper model, and the fallback model of the `fallback` catch up strategy
+    pub fn build(self) -> Result<Transcriber> {
+        let model = self
+            .model
+            .ok_or_else(|| Error::Config("path of whisper model is required".to_string()))?;
+        let latency = &self.config.latency;
+        latency.check().map_err(Error::Config)?;
+        let ctx = load_model(&model)?;
+
+        let fallback = match (&latency.fallback_model, latency.strategy) {
+            (Some(model), CatchUp::Fallback) => Some(Arc::new(load_model(model)?)),
+            (None, CatchUp::Fallback) if latency.max_lag_ms.is_some() => {
+                return Err(Error::Config(
+                    "fallback catch up strategy requires a fallback model".to_string(),
+                ))
+            }
+            _ => None,
+        };
+
+        Ok(Transcriber {
+            ctx: Arc::new(ctx),
+            fallback,
+            config: self.config,
+        })
+    }
+}
+
+fn load_model(model: &str) -> Result<WhisperContext> {
+    WhisperContext::new(model).map_err(|e| Error::Model(format!("{}: {:?}", model, e)))
+}
+
+/// streaming transcription pipeline, audio goes through vad into whisper
+#[derive(Clone)]
+pub struct Transcriber {
+    ctx: Arc<WhisperContext>,
+    /// smaller model used while a stream catches up
+    fallback: Option<Arc<WhisperContext>>,
+    config: Config,
+}
+
+impl Transcriber {
+    pub fn builder() -> TranscriberBuilder {
+        TranscriberBuilder::default()
+    }
+
+    /// spawn the vad and whisper threads, `f` receives every transcribed line
+    pub fn start<F>(&self, f: F) -> Session
+    where
+        F: FnMut(Transcript) + Send + 'static,
+    {
+        let (worker, rx) = mpsc::channel::<WorkerMessage>();
+        let handle_whisper = evoke_whisper_thread(self.models(), self.config.clone(), f, rx);
+
+        self.session(None, self.config.overflow, worker, Some(handle_whisper))
+    }
+
+    /// one whisper thread shared by the sessions of several streams,
+    /// `f` receives the lines of every stream labeled with the stream
+    pub fn scheduler<F>(&self, f: F) -> Scheduler
+    where
+        F: FnMut(Transcript) + Send + 'static,
+    {
+        let (worker, rx) = mpsc::channel::<WorkerMessage>();
+        let handle = evoke_whisper_thread(self.models(), self.config.clone(), f, rx);
+
+        Scheduler {
+            transcriber: self.clone(),
+            worker,
+            handle,
+        }
+    }
+
+    fn models(&self) -> (Arc<WhisperContext>, Option<Arc<WhisperContext>>) {
+        (self.ctx.clone(), self.fallback.clone())
+    }
+
+    /// spawn the vad thread of a stream and register it to the whisper thread
+    fn session(
+        &self,
+        label: Option<String>,
+        overflow: OverflowPolicy,
+        worker: Sender<WorkerMessage>,
+        handle_whisper: Option<JoinHandle<Result<()>>>,
+    ) -> Session {
+        // owned pcm chunks of about 30s audio data in total
+        let pcm = Arc::new(BoundedQueue::new(MAX_SAMPLE_RATE * 30, overflow));
+        // vad output in 20 segment
+        let segments = Arc::new(BoundedQueue::new(20, overflow));
+        let stats = Arc::new(Stats::default());
+        let (done_tx, done) = mpsc::sync_channel(1);
+
+        // a slot refused by a stopped whisper thread is dropped, which closes its queue
+        let _ = worker.send(WorkerMessage::Add(StreamSlot {
+            label,
+            segments: segments.clone(),
+            previous_text: String::new(),
+            language: None,
+            stats: stats.clone(),
+            monitor: LagMonitor::new(&self.config.latency),
+            _done: done_tx,
+        }));
+
+        let handle_vad = evoke_vad_thread(
+            self.config.clone(),
+            worker,
+            (pcm.clone(), segments),
+            stats.clone(),
+        );
+
+        Session {
+            stats,
+            pcm,
+            logger: self.config.logger.clone(),
+            drops: DropReport::new(self.config.logger.clone()),
+            handle_vad,
+            done,
+            handle_whisper,
+        }
+    }
+
+    /// transcribe the whole input, blocks until the input ends
+    pub fn transcribe<F>(&self, input: &mut Input, f: F) -> Result<()>
+    where
+        F: FnMut(Transcript) + Send + 'static,
+    {
+        let mut session = self.start(f);
+        let result = session.feed(input);
+        let finished = session.finish();
+        result.and(finished)
+    }
+
+    /// transcribe the input on a background thread and iterate the transcribed lines
+    pub fn stream(&self, mut input: Input) -> TranscriptStream {
+        let (tx, rx) = mpsc::channel();
+        let transcriber = self.clone();
+
+        let handle = thread::spawn(move || -> Result<()> {
+            let result = transcriber.transcribe(&mut input, move |transcript| {
+                let _ = tx.send(transcript);
+            });
+            // the transcription error comes first, a failed close only shows when it went fine
+            let closed = input.close();
+            result.and(closed.map_err(Into::into))
+        });
+
+        TranscriptStream { rx, handle }
+    }
+}
+
+/// whisper thread shared by the streams of several sessions,
+/// segments are taken from the streams in turn so a busy stream can't starve the others
+pub struct Scheduler {
+    transcriber: Transcriber,
+    worker: Sender<WorkerMessage>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl Scheduler {
+    /// spawn the vad thread of a stream, its lines are labeled with `label`
+    pub fn start<S: Into<String>>(&mut self, label: S) -> Session {
+        let overflow = self.transcriber.config.overflow;
+        self.start_with(label, overflow)
+    }
+
+    /// same as `start` with the overflow policy of this stream,
+    /// streams of different kinds can share the scheduler
+    pub fn start_with<S: Into<String>>(&mut self, label: S, overflow: OverflowPolicy) -> Session {
+        self.transcriber
+            .session(Some(label.into()), overflow, self.worker.clone(), None)
+    }
+
+    /// wait for the whisper thread once every session is finished
+    pub fn finish(self) -> Result<()> {
+        drop(self.worker);
+        let whisper = self.handle.join().map_err(|_| Error::Thread("whisper"))?;
+        whisper.map_err(|e| Error::Stage(Stage::Whisper, Box::new(e)))
+    }
+}
+
+/// running pipeline fed by the caller
+pub struct Session {
+    stats: Arc<Stats>,
+    pcm: PcmQueue,
+    logger: Log,
+    drops: DropReport,
+    handle_vad: JoinHandle<Result<()>>,
+    /// disconnected once whisper is done with the stream
+    done: Receiver<()>,
+    /// whisper thread of a session started without a scheduler
+    handle_whisper: Option<JoinHandle<Result<()>>>,
+}
+
+impl Session {
+    /// push mono pcm at `sample_rate`.
+    /// returns false when the pipeline is gone
+    pub fn push_pcm(&mut self, audio_data: &[f32], sample_rate: u32) -> bool {
+        let chunk = PcmChunk {
+            data: audio_data.to_vec(),
+            sample_rate,
+        };
+
+        // a marker keeps the dropped audio in the vad clock
+        let running = match self.pcm.push_marked(Pcm::Audio(chunk), Pcm::gap) {
+            Ok(dropped) => {
+                for pcm in dropped {
+                    if let Pcm::Audio(chunk) = pcm {
+                        self.stats.drop_audio(chunk.data.len(), chunk.sample_rate);
+                    }
+                }
+                true
+            }
+            Err(Closed) => false,
+        };
+
+        self.stats.pcm.set(self.pcm.len(), self.pcm.capacity());
+        self.drops.report(&self.stats, false);
+        running
+    }
+
+    /// audio of `duration` was lost before the next pcm, timestamps move on by it.
+    /// returns false when the pipeline is gone
+    pub fn push_gap(&mut self, duration: Duration) -> bool {
+        let samples = (duration.as_secs_f64() * vad::SAMPLE_RATE as f64) as u64;
+        self.pcm.push(Pcm::Gap(samples)).is_ok()
+    }
+
+    /// buffer levels and whisper timings of the pipeline
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// decode the input until it ends and push its audio into the pipeline
+    pub fn feed(&mut self, input: &mut Input) -> Result<()> {
+        let logger = self.logger.clone();
+        let queue = input.queue();
+        let lost = input.lost_time();
+        let mut push = |data: &[f32], rate: u32| {
+            logger.verbose(format!(
+                "Get {}kb audio data at {}hz from input",
+                data.len() * 4 / 1024,
+                rate
+            ));
+            if let Some((len, capacity)) = queue.as_ref().map(|queue| queue.level()) {
+                self.stats.input.set(len, capacity);
+            }
+            // a restarted downloader continues at the live edge
+            if let Some(lost) = lost.as_ref().map(LostTime::take).filter(|d| !d.is_zero()) {
+                logger.verbose(format!(
+                    "{}s of the stream lost by a restart",
+                    lost.as_secs()
+                ));
+                if !self.push_gap(lost) {
+                    return false;
+                }
+            }
+            self.push_pcm(data, rate)
+        };
+
+        match input.container.clone() {
+            Container::Ts => ingest_ts(&mut input.reader, push)?,
+            Container::Other(hint) => {
+                audio::decode_stream(input.take_reader(), hint.as_deref(), &mut push)?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// end the stream, wait until its queued audio is transcribed and get the first error
+    /// of the stages. the shared whisper thread of a scheduler keeps running for the other streams
+    pub fn finish(mut self) -> Result<()> {
+        // the vad thread drains the queued pcm before it ends
+        self.pcm.close();
+        let vad = self.handle_vad.join().map_err(|_| Error::Thread("vad"))?;
+        self.drops.report(&self.stats, true);
+
+        // whisper drops the stream after its last segment
+        let _ = self.done.recv();
+
+        let whisper = match self.handle_whisper {
+            Some(handle) => handle.join().map_err(|_| Error::Thread("whisper"))?,
+            None => Ok(()),
+        };
+
+        if let (Err(_), Err(e)) = (&vad, &whisper) {
+            self.logger.warn(format!("whisper stage failed: {}", e));
+        }
+        vad.map_err(|e| Error::Stage(Stage::Vad, Box::new(e)))?;
+        whisper.map_err(|e| Error::Stage(Stage::Whisper, Box::new(e)))
+    }
+}
+
+/// rate limited log of the audio a session dropped
+struct DropReport {
+    logger: Log,
+    /// time of the last report
+    at: Instant,
+    /// dropped audio and speech seconds of the last report
+    reported: (f64, f64),
+}
+
+impl DropReport {
+    fn new(logger: Log) -> DropReport {
+        DropReport {
+            logger,
+            at: Instant::now(),
+            reported: (0.0, 0.0),
+        }
+    }
+
+    /// log the audio dropped since the last report, at most once every `DROP_REPORT_INTERVAL`
+    fn report(&mut self, stats: &Stats, force: bool) {
+        let (audio, speech) = self.reported;
+        let dropped = (stats.dropped_audio_secs(), stats.dropped_speech_secs());
+        if dropped == self.reported || (!force && self.at.elapsed() < DROP_REPORT_INTERVAL) {
+            return;
+        }
+
+        self.logger.warn(format!(
+            "pipeline is slower than real time, dropped {:.1}s of audio and {:.1}s of speech in the last {}s, {} in total",
+            dropped.0 - audio,
+            dropped.1 - speech,
+            self.at.elapsed().as_secs(),
+            stats.drop_summary()
+        ));
+        self.at = Instant::now();
+        self.reported = dropped;
+    }
+}
+
+/// transcribed lines of `Transcriber::stream`.
+/// the lines just end when the transcription fails, call `join` after iterating to get the error
+pub struct TranscriptStream {
+    rx: Receiver<Transcript>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl TranscriptStream {
+    /// wait for the input to end and get the result of the transcription
+    pub fn join(self) -> Result<()> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(Error::Thread("transcription")),
+        }
+    }
+}
+
+impl Iterator for TranscriptStream {
+    type Item = Transcript;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+/// demux the mpeg-ts byte stream and push about 1s of its audio at a time
+fn ingest_ts<R, F>(reader: &mut R, mut f: F) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(&[f32], u32) -> bool,
+{
+    let mut demuxer = TsDemuxer::new();
+    let mut batcher = PcmBatcher::default();
+    let mut running = true;
+
+    while running {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            batcher.flush(&mut f);
+            break;
+        }
+
+        let len = buf.len();
+        demuxer.push(buf, |audio| {
+            running = running && batcher.push(audio, &mut f);
+        })?;
+        reader.consume(len);
+    }
+
+    Ok(())
+}
+
+/// closes the queues of a vad thread when it ends, also by an error or a panic,
+/// so its session stops pushing and whisper drops the stream after the last segment
+struct VadGuard {
+    pcm: PcmQueue,
+    segments: SegmentQueue,
+    worker: Sender<WorkerMessage>,
+}
+
+impl Drop for VadGuard {
+    fn drop(&mut self) {
+        self.pcm.close();
+        self.segments.close();
+        let _ = self.worker.send(WorkerMessage::Ready);
+    }
+}
+
+/// speech detection of one stream between the pcm and segment queues
+struct VadStage {
+    state: VadState,
+    /// converts the pcm to the vad rate, replaced when the rate changes
+    resampler: Option<StreamResampler>,
+    /// samples short of a full vad window, kept for the next pcm
+    window: LocalRb<f32, Vec<MaybeUninit<f32>>>,
+    segments: SegmentQueue,
+    worker: Sender<WorkerMessage>,
+    stats: Arc<Stats>,
+    logger: Log,
+}
+
+impl VadStage {
+    /// resample pushed audio for vad, or move on by the lost samples of a gap.
+    /// returns false when whisper closed the segment queue
+    fn receive(&mut self, pcm: Pcm) -> Result<bool> {
+        let chunk = match pcm {
+            Pcm::Audio(chunk) => chunk,
+            Pcm::Gap(samples) => return self.skip(samples),
+        };
+
+        let mut data = vec![];
+        if self.resampler.as_ref().map(|r| r.input_rate()) != Some(chunk.sample_rate) {
+            // tail of the previous rate
+            if let Some(mut resampler) = self.resampler.take() {
+                data = resampler.flush()?;
+            }
+            self.resampler = Some(StreamResampler::new(
+                chunk.sample_rate,
+                vad::SAMPLE_RATE as u32,
+            )?);
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            data.extend(resampler.process(&chunk.data)?);
+        }
+
+        self.process(data)
+    }
+
+    /// audio was lost, the samples before the gap end the pending speech
+    fn skip(&mut self, samples: u64) -> Result<bool> {
+        if let Some(mut resampler) = self.resampler.take() {
+            if !self.process(resampler.flush()?)? {
+                return Ok(false);
+            }
+        }
+
+        // the samples short of a window go into the gap
+        let rest = self.window.pop_iter().count() as u64;
+        let mut buf = vec![];
+        vad::skip(&mut self.state, rest + samples, &mut buf);
+
+        self.logger.verbose(format!(
+            "skip {}s of lost audio",
+            samples as f64 / vad::SAMPLE_RATE as f64
+        ));
+        self.push(buf)
+    }
+
+    /// detect speech in 16khz pcm and push the ended segments.
+    /// returns false when whisper closed the segment queue
+    fn process(&mut self, mut data: Vec<f32>) -> Result<bool> {
+        if self.window.len() > 0 {
+            data.splice(0..0, self.window.pop_iter().collect::<Vec<f32>>());
+        }
+
+        let (left, right) = split_audio_data_with_window_size(data);
+        if let Some(d) = right {
+            d.iter().for_each(|d| {
+                self.window.push_overwrite(*d);
+            })
+        }
+
+        let Some(data) = left else {
+            return Ok(true);
+        };
+
+        let mut buf = vec![];
+        let running_calc = Instant::now();
+        for data in data.chunks(WINDOW_SIZE_SAMPLES) {
+            vad::vad(&mut self.state, data.to_vec(), &mut buf)?;
+        }
+        self.drop_lost();
+
+        self.logger.verbose(format!(
+            "vad process time: {}s, detect {} segment",
+            running_calc.elapsed().as_secs(),
+            buf.len()
+        ));
+
+        self.push(buf)
+    }
+
+    /// end of the stream, detect the samples held by the resampler,
+    /// add the samples short of a window and force out the pending speech
+    fn finish(&mut self) -> Result<bool> {
+        if let Some(mut resampler) = self.resampler.take() {
+            if !self.process(resampler.flush()?)? {
+                return Ok(false);
+            }
+        }
+
+        let mut buf = vec![];
+        let rest = self.window.pop_iter().collect::<Vec<f32>>();
+        vad::push_tail(&mut self.state, &rest, &mut buf);
+        self.drop_lost();
+        vad::flush(&mut self.state, &mut buf);
+
+        if !buf.is_empty() {
+            self.logger
+                .verbose(format!("flush {} segment at the end of stream", buf.len()));
+        }
+        self.push(buf)
+    }
+
+    /// count the samples vad had no room for as dropped audio
+    fn drop_lost(&mut self) {
+        let lost = self.state.take_lost_samples();
+        self.stats
+            .drop_audio(lost as usize, vad::SAMPLE_RATE as u32);
+    }
+
+    fn push(&mut self, segments: Vec<vad::VadSegment>) -> Result<bool> {
+        let queued = Instant::now();
+        for segment in segments {
+            match self.segments.push(QueuedSegment { segment, queued }) {
+                Ok(dropped) => self
+                    .stats
+                    .drop_segments(dropped.iter().map(|queued| &queued.segment)),
+                Err(Closed) => return Ok(false),
+            }
+            self.stats
+                .segments
+                .set(self.segments.len(), self.segments.capacity());
+            let _ = self.worker.send(WorkerMessage::Ready);
+        }
+
+        Ok(true)
+    }
+}
+
+fn evoke_vad_thread(
+    config: Config,
+    worker: Sender<WorkerMessage>,
+    queues: (PcmQueue, SegmentQueue),
+    stats: Arc<Stats>,
+) -> JoinHandle<Result<()>> {
+    let (pcm, segments) = queues;
+
+    thread::spawn(move || -> Result<()> {
+        let _guard = VadGuard {
+            pcm: pcm.clone(),
+            segments: segments.clone(),
+            worker: worker.clone(),
+        };
+
+        let mut stage = VadStage {
+            state: VadState::new(config.vad.clone())?,
+            resampler: None,
+            window: LocalRb::new(WINDOW_SIZE_SAMPLES),
+            segments,
+            worker,
+            stats: stats.clone(),
+            logger: config.logger.clone(),
+        };
+
+        while let Some(item) = pcm.recv() {
+            stats.pcm.set(pcm.len(), pcm.capacity());
+            if !stage.receive(item)? {
+                return Ok(());
+            }
+        }
+
+        // whisper transcribes the last speech before the session finishes
+        stage.finish()?;
+        Ok(())
+    })
+}
+
+fn evoke_whisper_thread<F>(
+    models: (Arc<WhisperContext>, Option<Arc<WhisperContext>>),
+    config: Config,
+    mut f: F,
+    rx: Receiver<WorkerMessage>,
+) -> JoinHandle<Result<()>>
+where
+    F: FnMut(Transcript) + Send + 'static,
+{
+    let logger = config.logger.clone();
+
+    thread::spawn(move || -> Result<()> {
+        let (ctx, fallback) = models;
+        let mut state = ctx.create_state()?;
+        let mut fallback_state = fallback
+            .as_ref()
+            .map(|ctx| ctx.create_state())
+            .transpose()?;
+        let auto = config.lang == "auto";
+        let lang = if config.lang.starts_with("zh") {
+            "zh"
+        } else {
+            &config.lang
+        };
+
+        #[cfg(feature = "zh")]
+        let zh_transformer = crate::zh::ZHTransformer::from(if auto {
+            config.zh_variant.as_deref().unwrap_or("zh")
+        } else {
+            &config.lang
+        });
+
+        let mut transcribe = |stream: &mut StreamSlot, segment: vad::VadSegment| {
+            let running_calc = Instant::now();
+            let segment_time = segment.start_ms();
+
+            let catch_up = stream
+                .monitor
+                .catching_up()
+                .then_some(config.latency.strategy);
+            let whisper = match (catch_up, fallback_state.as_mut()) {
+                (Some(CatchUp::Fallback), Some(fallback)) => fallback,
+                _ => &mut state,
+            };
+            let decode = match catch_up {
+                Some(CatchUp::Fast) => config.decode.fast(),
+                _ => config.decode.clone(),
+            };
+
+            let (lang, language_probability) = if auto {
+                match speech::detect_language(whisper, &segment.data, config.threads) {
+                    Some((lang, p)) => {
+                        stream.language = Some(lang);
+                        (lang, Some(p))
+                    }
+                    // the stream likely goes on in the same language,
+                    // whisper detects it again while decoding before one is known
+                    None => (stream.language.unwrap_or("auto"), None),
+                }
+            } else {
+                (lang, None)
+            };
+
+            let prompt = build_prompt(
+                decode.initial_prompt.as_deref(),
+                decode
+                    .carry_context
+                    .then_some(stream.previous_text.as_str()),
+            );
+
+            let translate = config.task == Task::Translate;
+            let speech_config = |translate: bool| {
+                SpeechConfig::new(config.threads, Some(lang))
+                    .translate(translate)
+                    .decode(decode.clone())
+                    .prompt(prompt.as_deref())
+            };
+
+            // a failed segment is skipped, the next one may decode fine
+            let results = match run_whisper(whisper, speech_config(translate), &segment.data) {
+                Ok(results) => results,
+                Err(e) => {
+                    logger.warn(Error::from(e).to_string());
+                    return;
+                }
+            };
+            let translations = if config.task == Task::Both {
+                run_whisper(whisper, speech_config(true), &segment.data).unwrap_or_else(|e| {
+                    logger.warn(Error::from(e).to_string());
+                    vec![]
+                })
+            } else {
+                vec![]
+            };
+
+            stream.previous_text = results
+                .iter()
+                .map(|result| result.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let language = match lang {
+                _ if translate => "en",
+                "auto" => UNDETERMINED_LANGUAGE,
+                lang => lang,
+            };
+            let single = results.len() == 1;
+
+            for result in &results {
+                #[cfg(feature = "zh")]
+                let text = match &zh_transformer {
+                    Ok(zh) if !translate && lang == "zh" => zh.convert(&result.text),
+                    _ => result.text.to_string(),
+                };
+                #[cfg(not(feature = "zh"))]
+                let text = result.text.to_string();
+
+                let translation = (config.task == Task::Both)
+                    .then(|| overlapping_text(result, &translations, single));
+
+                f(Transcript {
+                    start: segment_time + result.start,
+                    end: (segment_time + result.end).min(segment.end_ms()),
+                    text,
+                    language: language.to_string(),
+                    language_probability,
+                    segment: segment.id,
+                    tokens: result.tokens.clone(),
+                    translation,
+                    stream: stream.label.clone(),
+                });
+            }
+
+            stream
+                .stats
+                .set_whisper(running_calc.elapsed().as_millis() as u64, language);
+            logger.verbose(format!(
+                "whisper process time: {}s",
+                running_calc.elapsed().as_secs()
+            ));
+        };
+
+        let mut streams: Vec<StreamSlot> = vec![];
+
+        loop {
+            // wait for the stages while no segment is queued,
+            // a disconnected channel means every vad thread has ended
+            if streams.iter().all(|stream| stream.segments.is_empty()) {
+                match rx.recv() {
+                    Ok(WorkerMessage::Add(stream)) => streams.push(stream),
+                    Ok(WorkerMessage::Ready) => (),
+                    Err(_) => break,
+                }
+            }
+            for message in rx.try_iter() {
+                if let WorkerMessage::Add(stream) = message {
+                    streams.push(stream);
+                }
+            }
+
+            // one segment of every stream in turn
+            for stream in streams.iter_mut() {
+                if let Some(QueuedSegment { segment, queued }) = stream.segments.try_recv() {
+                    stream
+                        .stats
+                        .segments
+                        .set(stream.segments.len(), stream.segments.capacity());
+
+                    let audio_ms = segment.end_ms() - segment.start_ms();
+                    let running_calc = Instant::now();
+                    transcribe(stream, segment);
+                    let rtf = latency::real_time_factor(running_calc.elapsed(), audio_ms);
+                    update_lag(
+                        stream,
+                        rtf,
+                        queued.elapsed(),
+                        config.latency.strategy,
+                        &logger,
+                    );
+                }
+            }
+
+            // dropping a stream after its last segment lets its session finish
+            streams.retain(|stream| !stream.segments.is_finished());
+        }
+
+        Ok(())
+    })
+}
+
+/// record the lag of the last segment of a stream and switch its catch up mode,
+/// `skip` drops the queued segments but the newest while the stream lags behind
+fn update_lag(stream: &mut StreamSlot, rtf: f64, lag: Duration, strategy: CatchUp, logger: &Log) {
+    let name = match &stream.label {
+        Some(label) => format!("stream {}", label),
+        None => "stream".to_string(),
+    };
+
+    match stream.monitor.update(lag) {
+        Some(true) => logger.warn(format!(
+            "{} lags {:.1}s behind at {:.2}x real time, catching up with {}",
+            name,
+            lag.as_secs_f64(),
+            rtf,
+            strategy
+        )),
+        Some(false) => logger.verbose(format!("{} caught up, lag {:.1}s", name, lag.as_secs_f64())),
+        None => (),
+    }
+
+    if stream.monitor.catching_up() && strategy == CatchUp::Skip {
+        let skipped = stream.segments.drain_but_last();
+        stream
+            .stats
+            .drop_segments(skipped.iter().map(|queued| &queued.segment));
+    }
+
+    stream
+        .stats
+        .set_lag(rtf, lag.as_millis() as u64, stream.monitor.catching_up());
+}
+
+fn run_whisper(
+    state: &mut WhisperState<'_>,
+    config: SpeechConfig,
+    data: &[f32],
+) -> std::result::Result<Vec<speech::Segment>, WhisperError> {
+    let mut payload = WhisperPayload::new(&data, config);
+    let mut segments = vec![];
+
+    speech::process(state, &mut payload, &mut |segment| {
+        segments.push(segment.clone())
+    })?;
+
+    Ok(segments)
+}
+
+/// initial prompt followed by the previous text
+fn build_prompt(initial_prompt: Option<&str>, previous_text: Option<&str>) -> Option<String> {
+    let prompt = [initial_prompt, previous_text]
+        .into_iter()
+        .flatten()
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!prompt.is_empty()).then_some(prompt)
+}
+
+/// text of the `others` segments spoken during `segment`, `all` takes every segment
+fn overlapping_text(segment: &speech::Segment, others: &[speech::Segment], all: bool) -> String {
+    others
+        .iter()
+        .filter(|other| {
+            let mid = (other.start + other.end) / 2;
+            all || (segment.start..segment.end).contains(&mid)
+        })
+        .map(|other| other.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, sync::Mutex};
+
+    use super::*;
+
+    fn test_input() -> Input {
+        let bytes = fs::read("tests/test.ts").unwrap();
+        Input::from_reader(Cursor::new(bytes), Container::Ts)
+    }
+
+    #[test]
+    fn test_ingest_reader() {
+        let mut input = test_input();
+        let mut samples = 0;
+        let mut rates = vec![];
+        ingest_ts(&mut input.reader, |data, rate| {
+            samples += data.len();
+            rates.push(rate);
+            true
+        })
+        .unwrap();
+
+        assert!(samples > 0);
+        assert!(rates.iter().all(|rate| *rate == rates[0]));
+    }
+
+    #[test]
+    #[ignore = "needs the path of a whisper model in WHISPER_MODEL"]
+    fn test_transcribe_reader() {
+        let model = std::env::var("WHISPER_MODEL").unwrap();
+        let transcriber = Transcriber::builder().model(model).build().unwrap();
+
+        let mut lines = transcriber.stream(test_input());
+        let count = lines.by_ref().count();
+        lines.join().unwrap();
+        assert!(count > 0);
+    }
+
+    fn slot(segments: &SegmentQueue, stats: &Arc<Stats>) -> StreamSlot {
+        StreamSlot {
+            label: None,
+            segments: segments.clone(),
+            previous_text: String::new(),
+            language: None,
+            stats: stats.clone(),
+            monitor: LagMonitor::new(&LatencyConfig {
+                max_lag_ms: Some(10000),
+                ..Default::default()
+            }),
+            _done: mpsc::sync_channel(1).0,
+        }
+    }
+
+    /// vad stage with a 960 samples buffer and 640 samples padding,
+    /// so every window fills the buffer without running the model
+    fn stage(segments: &SegmentQueue) -> VadStage {
+        let config = VadConfig {
+            max_segment_duration_ms: 60,
+            speech_pad_ms: 40,
+            ..VadConfig::default()
+        };
+        VadStage {
+            state: VadState::new(config).unwrap(),
+            resampler: None,
+            window: LocalRb::new(WINDOW_SIZE_SAMPLES),
+            segments: segments.clone(),
+            worker: mpsc::channel().0,
+            stats: Arc::new(Stats::default()),
+            logger: Log::quiet(),
+        }
+    }
+
+    #[test]
+    fn test_finish_partial_window() {
+        let segments: SegmentQueue = Arc::new(BoundedQueue::new(20, OverflowPolicy::Block));
+        // 160 samples shortest speech
+        let config = VadConfig {
+            min_speech_duration_ms: 10,
+            ..VadConfig::default()
+        };
+        let mut stage = VadStage {
+            state: VadState::new(config).unwrap(),
+            ..stage(&segments)
+        };
+        stage.state.trigger();
+
+        // short of a window, only kept until the stream ends
+        assert!(stage.process(vec![0.0; 320]).unwrap());
+        assert!(segments.is_empty());
+
+        // the partial window ends up in the flushed trailing speech
+        assert!(stage.finish().unwrap());
+        let segment = segments.try_recv().unwrap().segment;
+        assert_eq!((segment.start, segment.end), (0, 320));
+        assert_eq!(segment.data.len(), 320);
+        assert!(segments.is_empty());
+    }
+
+    #[test]
+    fn test_gap_of_dropped_audio() {
+        let pcm: PcmQueue = Arc::new(BoundedQueue::new(1024, OverflowPolicy::DropNewest));
+        let segments: SegmentQueue = Arc::new(BoundedQueue::new(20, OverflowPolicy::Block));
+        let mut stage = stage(&segments);
+        let mut buf = vec![];
+        vad::vad(&mut stage.state, vec![0.0; 960], &mut buf).unwrap();
+
+        // the later chunks don't fit and leave a single marker
+        let chunk = || {
+            Pcm::Audio(PcmChunk {
+                data: vec![0.0; 1024],
+                sample_rate: vad::SAMPLE_RATE as u32,
+            })
+        };
+        assert!(pcm.push_marked(chunk(), Pcm::gap).unwrap().is_empty());
+        assert_eq!(pcm.push_marked(chunk(), Pcm::gap).unwrap().len(), 1);
+        assert_eq!(pcm.push_marked(chunk(), Pcm::gap).unwrap().len(), 1);
+        let mut gaps = 0;
+        while let Some(item) = pcm.try_recv() {
+            gaps += matches!(item, Pcm::Gap(_)) as usize;
+            assert!(stage.receive(item).unwrap());
+        }
+        assert_eq!(gaps, 1);
+
+        // speech after the gap starts behind the received and the dropped audio
+        stage.state.trigger();
+        vad::vad(&mut stage.state, vec![0.0; 960], &mut buf).unwrap();
+        assert_eq!(buf.len(), 1);
+        assert_eq!(buf[0].start, 960 + 1024 + 1024 * 2);
+    }
+
+    /// session of stage threads made up by the test
+    fn session(
+        pcm: &PcmQueue,
+        handle_vad: JoinHandle<Result<()>>,
+        done: Receiver<()>,
+        handle_whisper: JoinHandle<Result<()>>,
+    ) -> Session {
+        Session {
+            stats: Arc::new(Stats::default()),
+            pcm: pcm.clone(),
+            logger: Log::quiet(),
+            drops: DropReport::new(Log::quiet()),
+            handle_vad,
+            done,
+            handle_whisper: Some(handle_whisper),
+        }
+    }
+
+    /// whisper thread which drops the stream once vad closed its segments
+    fn whisper(
+        segments: &SegmentQueue,
+        done: SyncSender<()>,
+        result: Result<()>,
+        events: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> JoinHandle<Result<()>> {
+        let (segments, events) = (segments.clone(), events.clone());
+        thread::spawn(move || {
+            while segments.recv().is_some() {}
+            events.lock().unwrap().push("whisper");
+            drop(done);
+            result
+        })
+    }
+
+    #[test]
+    fn test_finish_order() {
+        let pcm: PcmQueue = Arc::new(BoundedQueue::new(4096, OverflowPolicy::Block));
+        let segments: SegmentQueue = Arc::new(BoundedQueue::new(20, OverflowPolicy::Block));
+        let events = Arc::new(Mutex::new(vec![]));
+
+        // vad drains the queued pcm before it ends and closes the segments
+        let handle_vad = thread::spawn({
+            let (pcm, segments, events) = (pcm.clone(), segments.clone(), events.clone());
+            move || {
+                let mut received = 0;
+                while let Some(Pcm::Audio(chunk)) = pcm.recv() {
+                    received += chunk.data.len();
+                }
+                events.lock().unwrap().push("vad");
+                assert_eq!(received, 3000);
+                segments.close();
+                Ok(())
+            }
+        });
+        let (done_tx, done) = mpsc::sync_channel(1);
+        let handle_whisper = whisper(&segments, done_tx, Ok(()), &events);
+
+        let mut session = session(&pcm, handle_vad, done, handle_whisper);
+        for _ in 0..3 {
+            assert!(session.push_pcm(&[0.0; 1000], 16000));
+        }
+        session.finish().unwrap();
+        assert_eq!(*events.lock().unwrap(), ["vad", "whisper"]);
+    }
+
+    #[test]
+    fn test_stage_error() {
+        let pcm: PcmQueue = Arc::new(BoundedQueue::new(4096, OverflowPolicy::Block));
+        let segments: SegmentQueue = Arc::new(BoundedQueue::new(20, OverflowPolicy::Block));
+        let events = Arc::new(Mutex::new(vec![]));
+
+        // vad fails, which closes its queues like its guard does
+        let handle_vad = thread::spawn({
+            let (pcm, segments) = (pcm.clone(), segments.clone());
+            move || {
+                pcm.close();
+                segments.close();
+                Err(Error::Audio(audio::Error::Empty))
+            }
+        });
+        let (done_tx, done) = mpsc::sync_channel(1);
+        let failed = Err(Error::Thread("whisper"));
+        let handle_whisper = whisper(&segments, done_tx, failed, &events);
+        let mut session = session(&pcm, handle_vad, done, handle_whisper);
+
+        // the first failure reaches the caller of the stream
+        let stream = TranscriptStream {
+            rx: mpsc::channel().1,
+            handle: thread::spawn(move || {
+                while session.push_pcm(&[0.0; 1000], 16000) {}
+                session.finish()
+            }),
+        };
+        match stream.join() {
+            Err(Error::Stage(Stage::Vad, e)) => assert!(matches!(*e, Error::Audio(_))),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_skip_lagging_segments() {
+        let segments: SegmentQueue = Arc::new(BoundedQueue::new(20, OverflowPolicy::Block));
+        let stats = Arc::new(Stats::default());
+        let mut stream = slot(&segments, &stats);
+        let queued = Instant::now();
+        for id in 1..=3 {
+            let segment = vad::VadSegment {
+                id,
+                ..Default::default()
+            };
+            assert!(segments.push(QueuedSegment { segment, queued }).is_ok());
+        }
+
+        // in time, nothing is skipped
+        let logger = Log::quiet();
+        update_lag(
+            &mut stream,
+            1.0,
+            Duration::from_secs(5),
+            CatchUp::Skip,
+            &logger,
+        );
+        assert_eq!(segments.len(), 3);
+
+        // lagging behind keeps the newest segment, even once vad closed the queue
+        segments.close();
+        update_lag(
+            &mut stream,
+            2.0,
+            Duration::from_secs(12),
+            CatchUp::Skip,
+            &logger,
+        );
+        assert_eq!(stats.dropped_segments(), 2);
+        assert_eq!(segments.recv().map(|queued| queued.segment.id), Some(3));
+        assert!(segments.is_finished());
+    }
+}
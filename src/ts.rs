@@ -0,0 +1,387 @@
+use symphonia::core::{
+    audio::{AudioBuffer, Channels},
+    codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_AAC},
+    errors::Error as SymphoniaError,
+    formats::Packet,
+};
+
+use crate::audio::Error;
+
+/// mpeg-ts packet size
+pub(crate) const PACKET_SIZE: usize = 188;
+/// mpeg-ts sync byte
+pub(crate) const SYNC_BYTE: u8 = 0x47;
+/// pid of the program association table
+const PAT_PID: u16 = 0;
+/// pmt stream type of adts aac
+const STREAM_TYPE_AAC: u8 = 0x0f;
+/// samples of an aac frame
+const AAC_FRAME_SAMPLES: u64 = 1024;
+/// sample rates of the adts sampling frequency index
+const ADTS_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// streaming demuxer of the adts aac audio in mpeg-ts,
+/// partial ts packets and adts frames are carried over to the next chunk
+/// and a single decoder is kept for the whole stream
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// bytes of an incomplete ts packet
+    pending: Vec<u8>,
+    pmt_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    /// continuity counter of the last audio packet
+    continuity: Option<u8>,
+    /// pes payload not yet split into adts frames
+    adts: Vec<u8>,
+    decoder: Option<AacDecoder>,
+}
+
+struct AacDecoder {
+    decoder: Box<dyn Decoder>,
+    sample_rate: u32,
+    channels: u8,
+    ts: u64,
+}
+
+/// fixed header of an adts frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AdtsHeader {
+    sample_rate: u32,
+    channels: u8,
+    header_len: usize,
+    frame_len: usize,
+}
+
+impl TsDemuxer {
+    pub fn new() -> TsDemuxer {
+        TsDemuxer::default()
+    }
+
+    /// sample rate of the decoded audio once the first frame is decoded
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.decoder.as_ref().map(|decoder| decoder.sample_rate)
+    }
+
+    /// demux and decode the next bytes of the stream,
+    /// `f` receives the decoded buffer of every aac frame
+    pub fn push<F>(&mut self, data: &[u8], mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&AudioBuffer<f32>),
+    {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(data);
+
+        let mut offset = 0;
+        let mut result = Ok(());
+        while pending.len() - offset >= PACKET_SIZE {
+            let next = pending.get(offset + PACKET_SIZE);
+            if pending[offset] != SYNC_BYTE || next.is_some_and(|b| *b != SYNC_BYTE) {
+                // realign to the packet boundary
+                offset += find_sync(&pending[offset..]).unwrap_or(pending.len() - offset);
+                continue;
+            }
+
+            result = self.read_packet(&pending[offset..offset + PACKET_SIZE], &mut f);
+            offset += PACKET_SIZE;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        pending.drain(..offset);
+        self.pending = pending;
+        result
+    }
+
+    fn read_packet<F>(&mut self, packet: &[u8], f: &mut F) -> Result<(), Error>
+    where
+        F: FnMut(&AudioBuffer<f32>),
+    {
+        let transport_error = packet[1] & 0x80 != 0;
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = ((packet[1] as u16 & 0x1f) << 8) | packet[2] as u16;
+        let adaptation = (packet[3] >> 4) & 0x03;
+        let continuity = packet[3] & 0x0f;
+
+        if transport_error || adaptation & 0x01 == 0 {
+            return Ok(());
+        }
+
+        let start = if adaptation & 0x02 != 0 {
+            5 + packet[4] as usize
+        } else {
+            4
+        };
+        let Some(payload) = packet.get(start..) else {
+            return Ok(());
+        };
+
+        if pid == PAT_PID {
+            if unit_start {
+                self.pmt_pid = parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start {
+                self.audio_pid = parse_pmt(payload);
+            }
+        } else if Some(pid) == self.audio_pid {
+            // drop the partial frame when packets were lost
+            let expected = self.continuity.map(|last| (last + 1) & 0x0f);
+            if expected.is_some_and(|expected| expected != continuity) {
+                self.adts.clear();
+            }
+            self.continuity = Some(continuity);
+
+            if unit_start {
+                match parse_pes_payload(payload) {
+                    Some(data) => self.adts.extend_from_slice(data),
+                    None => self.adts.clear(),
+                }
+            } else {
+                self.adts.extend_from_slice(payload);
+            }
+
+            self.decode_frames(f)?;
+        }
+
+        Ok(())
+    }
+
+    /// decode every complete adts frame in the pes payload
+    fn decode_frames<F>(&mut self, f: &mut F) -> Result<(), Error>
+    where
+        F: FnMut(&AudioBuffer<f32>),
+    {
+        let mut offset = 0;
+
+        while self.adts.len() - offset >= 7 {
+            let data = &self.adts[offset..];
+            let Some(header) = parse_adts_header(data) else {
+                offset += 1;
+                continue;
+            };
+
+            if data.len() < header.frame_len {
+                break;
+            }
+
+            let frame = &data[header.header_len..header.frame_len];
+            offset += header.frame_len;
+
+            let mut decoder = match self.decoder.take() {
+                Some(decoder) if decoder.accepts(&header) => decoder,
+                // the first frame or the audio format changed
+                _ => AacDecoder::new(&header)?,
+            };
+            decoder.decode(frame, f);
+            self.decoder = Some(decoder);
+        }
+
+        self.adts.drain(..offset);
+        Ok(())
+    }
+}
+
+impl AacDecoder {
+    fn new(header: &AdtsHeader) -> Result<AacDecoder, Error> {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_AAC)
+            .with_sample_rate(header.sample_rate)
+            .with_channels(channel_mask(header.channels));
+
+        let decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|_| Error::Decoder)?;
+
+        Ok(AacDecoder {
+            decoder,
+            sample_rate: header.sample_rate,
+            channels: header.channels,
+            ts: 0,
+        })
+    }
+
+    fn accepts(&self, header: &AdtsHeader) -> bool {
+        self.sample_rate == header.sample_rate && self.channels == header.channels
+    }
+
+    fn decode<F>(&mut self, frame: &[u8], f: &mut F)
+    where
+        F: FnMut(&AudioBuffer<f32>),
+    {
+        let packet = Packet::new_from_slice(0, self.ts, AAC_FRAME_SAMPLES, frame);
+        self.ts += AAC_FRAME_SAMPLES;
+
+        match self.decoder.decode(&packet) {
+            Ok(audio_buf) => {
+                let mut buf =
+                    AudioBuffer::<f32>::new(audio_buf.capacity() as u64, *audio_buf.spec());
+                audio_buf.convert(&mut buf);
+                f(&buf);
+            }
+            // a broken frame is skipped
+            Err(SymphoniaError::DecodeError(_)) => (),
+            Err(_) => self.decoder.reset(),
+        }
+    }
+}
+
+/// offset of the next sync byte followed by another one a packet later
+fn find_sync(buf: &[u8]) -> Option<usize> {
+    (1..buf.len()).find(|&i| {
+        buf[i] == SYNC_BYTE && buf.get(i + PACKET_SIZE).map_or(true, |b| *b == SYNC_BYTE)
+    })
+}
+
+/// skip the pointer field of a psi section
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let len = ((section.get(1)? & 0x0f) as usize) << 8 | *section.get(2)? as usize;
+
+    // without the trailing crc
+    section.get(..(3 + len).checked_sub(4)?)
+}
+
+/// pid of the pmt of the first program
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let section = section(payload)?;
+
+    section.get(8..)?.chunks_exact(4).find_map(|program| {
+        let number = (program[0] as u16) << 8 | program[1] as u16;
+        let pid = ((program[2] as u16 & 0x1f) << 8) | program[3] as u16;
+        (number != 0).then_some(pid)
+    })
+}
+
+/// pid of the first adts aac stream
+fn parse_pmt(payload: &[u8]) -> Option<u16> {
+    let section = section(payload)?;
+    let info_len = ((*section.get(10)? as usize & 0x0f) << 8) | *section.get(11)? as usize;
+
+    let mut offset = 12 + info_len;
+    while let Some(stream) = section.get(offset..offset + 5) {
+        let stream_type = stream[0];
+        let pid = ((stream[1] as u16 & 0x1f) << 8) | stream[2] as u16;
+        let es_info_len = ((stream[3] as usize & 0x0f) << 8) | stream[4] as usize;
+
+        if stream_type == STREAM_TYPE_AAC {
+            return Some(pid);
+        }
+
+        offset += 5 + es_info_len;
+    }
+
+    None
+}
+
+/// data after the header of a pes packet
+fn parse_pes_payload(payload: &[u8]) -> Option<&[u8]> {
+    if payload.get(..3)? != [0, 0, 1] {
+        return None;
+    }
+
+    let header_len = *payload.get(8)? as usize;
+    payload.get(9 + header_len..)
+}
+
+/// speaker positions of an adts channel configuration
+fn channel_mask(channels: u8) -> Channels {
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let side = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+    match channels {
+        1 => Channels::FRONT_LEFT,
+        2 => front,
+        3 => Channels::FRONT_CENTRE | front,
+        4 => Channels::FRONT_CENTRE | front | Channels::REAR_CENTRE,
+        5 => Channels::FRONT_CENTRE | front | side,
+        _ => Channels::FRONT_CENTRE | front | side | Channels::LFE1,
+    }
+}
+
+fn parse_adts_header(data: &[u8]) -> Option<AdtsHeader> {
+    if data.len() < 7 || data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+        return None;
+    }
+
+    let protection_absent = data[1] & 0x01 != 0;
+    let sample_rate = *ADTS_SAMPLE_RATES.get(((data[2] >> 2) & 0x0f) as usize)?;
+    let channels = ((data[2] & 0x01) << 2) | (data[3] >> 6);
+    let frame_len =
+        ((data[3] as usize & 0x03) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+    let header_len = if protection_absent { 7 } else { 9 };
+
+    // configuration 0 is described in the stream and 7 isn't decoded
+    if frame_len <= header_len || !(1..=6).contains(&channels) {
+        return None;
+    }
+
+    Some(AdtsHeader {
+        sample_rate,
+        channels,
+        header_len,
+        frame_len,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::Signal;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_adts_header() {
+        // 22050hz mono, 371 bytes without crc
+        let header = parse_adts_header(&[0xff, 0xf1, 0x5c, 0x40, 0x2e, 0x7f, 0xfc]).unwrap();
+        assert_eq!(header.sample_rate, 22050);
+        assert_eq!(header.channels, 1);
+        assert_eq!(header.header_len, 7);
+        assert_eq!(header.frame_len, 371);
+
+        // 5.1 surround
+        let header = parse_adts_header(&[0xff, 0xf1, 0x5d, 0x80, 0x2e, 0x7f, 0xfc]).unwrap();
+        assert_eq!(header.channels, 6);
+        assert_eq!(header.frame_len, 371);
+        assert_eq!(channel_mask(header.channels).count(), 6);
+
+        // channel configurations 0 and 7
+        assert_eq!(
+            parse_adts_header(&[0xff, 0xf1, 0x5c, 0x00, 0x2e, 0x7f, 0xfc]),
+            None
+        );
+        assert_eq!(
+            parse_adts_header(&[0xff, 0xf1, 0x5d, 0xc0, 0x2e, 0x7f, 0xfc]),
+            None
+        );
+
+        assert_eq!(
+            parse_adts_header(&[0x47, 0xf1, 0x5c, 0x40, 0x2e, 0x7f, 0xfc]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_demux_split_chunks() {
+        let data = std::fs::read("tests/test.ts").unwrap();
+
+        let mut whole = 0;
+        TsDemuxer::new()
+            .push(&data, |buf| whole += buf.frames())
+            .unwrap();
+
+        // odd chunk sizes split packets and frames at every boundary
+        let mut split = 0;
+        let mut demuxer = TsDemuxer::new();
+        for chunk in data.chunks(1000) {
+            demuxer.push(chunk, |buf| split += buf.frames()).unwrap();
+        }
+
+        assert!(whole > 0);
+        assert_eq!(whole, split);
+        assert!(demuxer.sample_rate().is_some());
+    }
+}
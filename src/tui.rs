@@ -0,0 +1,523 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    ops::Range,
+    sync::{mpsc::Receiver, Arc},
+    time::Duration,
+};
+
+use ratatui::{
+    crossterm::event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    stats::{Level, Stats},
+    Transcript,
+};
+
+/// lines kept in the transcript pane, the oldest are dropped first
+const MAX_LINES: usize = 100_000;
+/// lines moved by page up and down
+const PAGE: usize = 20;
+/// redraw interval while no key is pressed
+const TICK: Duration = Duration::from_millis(200);
+
+/// updates of the transcription pipeline shown by the terminal ui
+pub enum Event {
+    /// a stream started, with its label when several streams are transcribed
+    Stream(Option<String>, Arc<Stats>),
+    /// a transcribed line with its displayed timestamp
+    Line(String, Transcript),
+    /// an error of a stream which doesn't stop the others
+    Error(String),
+    /// the transcription ended, with its error if it failed
+    End(Option<String>),
+}
+
+/// a line of the transcript pane
+struct Entry {
+    time: String,
+    transcript: Transcript,
+}
+
+impl Entry {
+    /// rows taken in the transcript pane
+    fn rows(&self) -> usize {
+        1 + self.transcript.translation.is_some() as usize
+    }
+}
+
+/// state of the terminal ui apart from the terminal itself
+#[derive(Default)]
+struct App {
+    entries: VecDeque<Entry>,
+    /// first line shown in the transcript pane
+    offset: usize,
+    streams: Vec<(Option<String>, Arc<Stats>)>,
+    /// selected line, the view follows the newest line while it's `None`
+    selected: Option<usize>,
+    /// the view stays where it is while new lines come in
+    paused: bool,
+    /// lines received while paused
+    unseen: usize,
+    query: Option<String>,
+    /// query being typed
+    search: Option<String>,
+    /// last message of the status line
+    message: Option<String>,
+    ended: bool,
+    quit: bool,
+}
+
+impl App {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Stream(label, stats) => self.streams.push((label, stats)),
+            Event::Line(time, transcript) => self.push(Entry { time, transcript }),
+            Event::Error(e) => self.message = Some(e),
+            Event::End(error) => {
+                self.ended = true;
+                self.message = Some(match error {
+                    Some(e) => format!("ended with error: {}", e),
+                    None => "transcription ended, press q to quit".to_string(),
+                });
+            }
+        }
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_LINES {
+            self.entries.pop_front();
+            self.selected = self.selected.map(|i| i.saturating_sub(1));
+            self.offset = self.offset.saturating_sub(1);
+        }
+
+        if self.paused {
+            self.unseen += 1;
+        }
+    }
+
+    /// line shown as selected
+    fn current(&self) -> Option<usize> {
+        self.selected.or(self.entries.len().checked_sub(1))
+    }
+
+    /// lines which fit a transcript pane of `height` rows, scrolled so the current line is shown
+    fn window(&mut self, height: usize) -> Range<usize> {
+        let Some(current) = self.current() else {
+            return 0..0;
+        };
+        let rows = |i: usize| self.entries[i].rows();
+
+        // every line takes a row at least, lines further away can't be shown
+        self.offset = self.offset.clamp(current.saturating_sub(height), current);
+        let mut used = (self.offset..=current).map(rows).sum::<usize>();
+        while used > height && self.offset < current {
+            used -= rows(self.offset);
+            self.offset += 1;
+        }
+
+        let mut end = current + 1;
+        while end < self.entries.len() && used + rows(end) <= height {
+            used += rows(end);
+            end += 1;
+        }
+
+        self.offset..end
+    }
+
+    /// move the selection, reaching the newest line follows it again unless paused
+    fn scroll(&mut self, delta: isize) {
+        let Some(current) = self.current() else {
+            return;
+        };
+
+        let last = self.entries.len() - 1;
+        let next = current.saturating_add_signed(delta).min(last);
+        self.selected = (next != last || self.paused).then_some(next);
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.unseen = 0;
+        self.selected = if self.paused { self.current() } else { None };
+    }
+
+    /// select the closest line matching the query before the selection, or after it when `forward`
+    fn find(&mut self, forward: bool) {
+        let Some(query) = self.query.as_ref().map(|q| q.to_lowercase()) else {
+            return;
+        };
+        let Some(current) = self.current() else {
+            return;
+        };
+
+        let matches = |entry: &Entry| {
+            entry.transcript.text.to_lowercase().contains(&query)
+                || entry
+                    .transcript
+                    .translation
+                    .as_ref()
+                    .is_some_and(|t| t.to_lowercase().contains(&query))
+        };
+
+        let found = if forward {
+            (current + 1..self.entries.len()).find(|&i| matches(&self.entries[i]))
+        } else {
+            (0..current).rev().find(|&i| matches(&self.entries[i]))
+        };
+
+        match found {
+            Some(i) => {
+                self.selected = Some(i);
+                self.message = None;
+            }
+            None => self.message = Some(format!("no more lines matching \"{}\"", query)),
+        }
+    }
+
+    /// text of the selected line copied to the clipboard
+    fn selected_text(&self) -> Option<String> {
+        let entry = &self.entries[self.current()?];
+        let mut text = entry.transcript.text.clone();
+        if let Some(translation) = &entry.transcript.translation {
+            text.push('\n');
+            text.push_str(translation);
+        }
+        Some(text)
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        if let Some(query) = self.search.as_mut() {
+            match key.code {
+                KeyCode::Enter => {
+                    self.query = self.search.take().filter(|q| !q.is_empty());
+                    self.find(false);
+                }
+                KeyCode::Esc => self.search = None,
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Char(c) => query.push(c),
+                _ => (),
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.toggle_pause(),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll(1),
+            KeyCode::PageUp => self.scroll(-(PAGE as isize)),
+            KeyCode::PageDown => self.scroll(PAGE as isize),
+            KeyCode::Home | KeyCode::Char('g') if !self.entries.is_empty() => {
+                self.selected = Some(0)
+            }
+            KeyCode::End | KeyCode::Char('G') => {
+                self.paused = false;
+                self.unseen = 0;
+                self.selected = None;
+            }
+            KeyCode::Char('/') => self.search = Some(String::new()),
+            KeyCode::Char('n') => self.find(false),
+            KeyCode::Char('N') => self.find(true),
+            KeyCode::Char('y') => {
+                if let Some(text) = self.selected_text() {
+                    self.message = Some(match copy(&text) {
+                        Ok(_) => "copied the selected line".to_string(),
+                        Err(e) => format!("failed to copy: {}", e),
+                    });
+                }
+            }
+            KeyCode::Esc => self.message = None,
+            _ => (),
+        }
+    }
+}
+
+/// show the transcript until the user quits.
+/// returns true when the transcription ended before that
+pub fn run(rx: Receiver<Event>) -> io::Result<bool> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, rx);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, rx: Receiver<Event>) -> io::Result<bool> {
+    let mut app = App::default();
+
+    while !app.quit {
+        while let Ok(event) = rx.try_recv() {
+            app.handle(event);
+        }
+
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        if event::poll(TICK)? {
+            if let TermEvent::Key(key) = event::read()? {
+                app.key(key);
+            }
+        }
+    }
+
+    Ok(app.ended)
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [transcript, status, footer] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(app.streams.len().clamp(1, 4) as u16),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    // only the shown lines are built, the pane holds a slice of the transcript
+    let window = app.window(transcript.height.saturating_sub(2) as usize);
+    let items = app.entries.range(window.clone()).map(|entry| {
+        let cue = &entry.transcript;
+        let mut spans = vec![Span::styled(
+            format!("[{}] ", entry.time),
+            Style::default().fg(Color::LightYellow),
+        )];
+        if let Some(stream) = &cue.stream {
+            spans.push(Span::styled(
+                format!("[{}] ", stream),
+                Style::default().fg(Color::LightMagenta),
+            ));
+        }
+        if let Some(p) = cue.language_probability {
+            spans.push(Span::styled(
+                format!("({} {:.2}) ", cue.language, p),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        spans.push(Span::raw(cue.text.clone()));
+
+        let mut lines = vec![Line::from(spans)];
+        if let Some(translation) = &cue.translation {
+            lines.push(Line::styled(
+                format!("  {}", translation),
+                Style::default().fg(Color::LightCyan),
+            ));
+        }
+        ListItem::new(lines)
+    });
+
+    let title = match (app.paused, app.unseen) {
+        (true, 0) => " transcript [paused] ".to_string(),
+        (true, unseen) => format!(" transcript [paused, {} new] ", unseen),
+        (false, _) => " transcript ".to_string(),
+    };
+    let list = List::new(items)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    let mut state = ListState::default().with_selected(app.current().map(|i| i - window.start));
+    frame.render_stateful_widget(list, transcript, &mut state);
+
+    let status_lines = app
+        .streams
+        .iter()
+        .map(|(label, stats)| status_line(label.as_deref(), stats))
+        .collect::<Vec<_>>();
+    frame.render_widget(Paragraph::new(status_lines), status);
+
+    let footer_line = match (&app.search, &app.message) {
+        (Some(query), _) => Line::from(format!("/{}", query)),
+        (_, Some(message)) => Line::from(message.clone().yellow()),
+        _ => Line::from(
+            "q quit  space pause  ↑↓ pgup pgdn g G move  / search  n N next  y copy".dark_gray(),
+        ),
+    };
+    frame.render_widget(Paragraph::new(footer_line), footer);
+}
+
+/// buffer levels, whisper latency and lag, language and dropped audio of a stream
+fn status_line<'a>(label: Option<&str>, stats: &Stats) -> Line<'a> {
+    let mut spans = vec![];
+    if let Some(label) = label {
+        spans.push(Span::styled(
+            format!("{} ", label),
+            Style::default().fg(Color::LightMagenta),
+        ));
+    }
+
+    spans.push(level_span("input", &stats.input, false));
+    spans.push(level_span("pcm", &stats.pcm, true));
+    spans.push(level_span("vad", &stats.segments, false));
+    spans.push(Span::raw(format!(
+        "whisper {}ms rtf {:.2} lag {:.1}s ",
+        stats.whisper_ms(),
+        stats.rtf(),
+        stats.lag_ms() as f64 / 1000.0
+    )));
+    if stats.catching_up() {
+        spans.push(Span::styled(
+            "catching up ",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    spans.push(Span::raw(format!(
+        "lang {}",
+        stats.language().unwrap_or_else(|| "-".to_string())
+    )));
+
+    let dropped = stats.dropped_audio_secs() + stats.dropped_speech_secs();
+    if dropped > 0.0 {
+        spans.push(Span::styled(
+            format!(" dropped {:.1}s", dropped),
+            Style::default().fg(Color::Red),
+        ));
+    }
+
+    Line::from(spans)
+}
+
+/// `name len/capacity` or `name n%`, red once the queue is almost full
+fn level_span<'a>(name: &str, level: &Level, percent: bool) -> Span<'a> {
+    let (len, capacity) = level.get();
+    let text = if percent {
+        format!("{} {:.0}% ", name, level.ratio() * 100.0)
+    } else {
+        format!("{} {}/{} ", name, len, capacity)
+    };
+
+    let color = match level.ratio() {
+        r if r >= 0.8 => Color::Red,
+        r if r >= 0.5 => Color::Yellow,
+        _ => Color::Green,
+    };
+    Span::styled(text, Style::default().fg(color))
+}
+
+/// copy through the osc 52 escape sequence of the terminal, which also works over ssh
+fn copy(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Event {
+        Event::Line("00:00:00.000".to_string(), Transcript::test(text, 0, 0))
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("字幕".as_bytes()), "5a2X5bmV");
+    }
+
+    #[test]
+    fn test_follow_and_search() {
+        let mut app = App::default();
+        for text in ["hello", "world", "Hello again", "bye"] {
+            app.handle(line(text));
+        }
+        assert_eq!(app.current(), Some(3));
+
+        app.query = Some("hello".to_string());
+        app.find(false);
+        assert_eq!(app.current(), Some(2));
+        app.find(false);
+        assert_eq!(app.current(), Some(0));
+        app.find(true);
+        assert_eq!(app.current(), Some(2));
+
+        // new lines don't move the selection while going back
+        app.handle(line("new"));
+        assert_eq!(app.current(), Some(2));
+
+        // reaching the newest line follows again
+        app.scroll(10);
+        assert_eq!(app.selected, None);
+        app.handle(line("newer"));
+        assert_eq!(app.current(), Some(5));
+    }
+
+    #[test]
+    fn test_window() {
+        let mut app = App::default();
+        assert_eq!(app.window(3), 0..0);
+
+        for i in 0..10 {
+            app.handle(line(&i.to_string()));
+        }
+        // follows the newest line
+        assert_eq!(app.window(3), 7..10);
+
+        // moving up inside the pane keeps it, above it scrolls
+        app.scroll(-2);
+        assert_eq!(app.window(3), 7..10);
+        app.scroll(-3);
+        assert_eq!(app.window(3), 4..7);
+
+        // lines with a translation take two rows
+        app.handle(Event::Line(
+            "00:00:00.000".to_string(),
+            Transcript {
+                translation: Some("translated".to_string()),
+                ..Transcript::test("10", 0, 0)
+            },
+        ));
+        app.scroll(isize::MAX);
+        assert_eq!(app.window(3), 9..11);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut app = App::default();
+        app.handle(line("a"));
+        app.toggle_pause();
+        app.handle(line("b"));
+
+        assert_eq!(app.current(), Some(0));
+        assert_eq!(app.unseen, 1);
+
+        app.toggle_pause();
+        assert_eq!(app.current(), Some(1));
+    }
+}
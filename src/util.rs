@@ -3,31 +3,61 @@ use owo_colors::OwoColorize;
 #[derive(Clone, Default)]
 pub struct Log {
     enable: bool,
+    /// not even warnings are shown, the terminal is taken by a ui
+    quiet: bool,
 }
 
 impl Log {
     pub fn new(enable: bool) -> Log {
-        Log { enable }
+        Log {
+            enable,
+            quiet: false,
+        }
+    }
+
+    pub fn quiet() -> Log {
+        Log {
+            enable: false,
+            quiet: true,
+        }
     }
 
+    /// on stderr, stdout may carry the transcript
     pub fn verbose<S: AsRef<str>>(&self, msg: S) {
         if self.enable {
-            println!("{} {}", "[verbose]".green(), msg.as_ref().green());
+            eprintln!("{} {}", "[verbose]".green(), msg.as_ref().green());
         }
     }
 
     pub fn error<S: AsRef<str>>(&self, msg: S) {
         if self.enable {
-            println!("{} {}", "[error]".red(), msg.as_ref().red().bold());
+            eprintln!("{} {}", "[error]".red(), msg.as_ref().red().bold());
+        }
+    }
+
+    /// shown without verbose as well, on stderr
+    pub fn warn<S: AsRef<str>>(&self, msg: S) {
+        if !self.quiet {
+            eprintln!("{} {}", "[warn]".yellow(), msg.as_ref().yellow());
         }
     }
 }
 
+/// `HH:MM:SS.mmm`, hours keep growing past 24 for long streams
 pub fn format_timestamp_to_time(ms: i64) -> String {
+    format_timestamp(ms, '.')
+}
+
+/// `HH:MM:SS<sep>mmm`
+pub fn format_timestamp(ms: i64, sep: char) -> String {
+    let hours = ms / 3600000;
     let minutes = (ms / 60000) % 60;
     let seconds = (ms / 1000) % 60;
     let milliseconds = ms % 1000;
-    format!("{:02}:{:02}:{:03}", minutes, seconds, milliseconds)
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        hours, minutes, seconds, sep, milliseconds
+    )
 }
 
 #[cfg(test)]
@@ -38,6 +68,10 @@ mod tests {
     fn test_format_timestamp_to_time() {
         let time = 1000 * 60 * 60 * 3 + 1000 * 60 * 3 + 3;
         let result = format_timestamp_to_time(time);
-        assert_eq!(result, "03:00:003");
+        assert_eq!(result, "03:03:00.003");
+
+        let time = 1000 * 60 * 60 * 100 + 1000 * 59 + 999;
+        let result = format_timestamp_to_time(time);
+        assert_eq!(result, "100:00:59.999");
     }
 }
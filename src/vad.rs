@@ -1,6 +1,7 @@
 use std::{io::Cursor, mem::MaybeUninit, rc::Rc};
 
 use ringbuf::{Consumer, LocalRb, Producer};
+use serde::Deserialize;
 use tract_onnx::{
     prelude::{tract_itertools::Itertools, *},
     tract_hir::tract_ndarray::Array,
@@ -15,25 +16,83 @@ type OnnxModel = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dy
 type F32RingBufProducer = Producer<f32, Rc<LocalRb<f32, Vec<MaybeUninit<f32>>>>>;
 type F32RingBufConsumer = Consumer<f32, Rc<LocalRb<f32, Vec<MaybeUninit<f32>>>>>;
 
+/// speech detection parameters, durations are in ms
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VadConfig {
+    /// speech probability to start a speech
+    pub threshold: f32,
+    /// speech probability to end a speech
+    pub neg_threshold: f32,
+    /// silence needed after a speech to end the segment
+    pub min_silence_duration_ms: u32,
+    /// shorter speech is dropped
+    pub min_speech_duration_ms: u32,
+    /// longer speech is split into segments of this duration
+    pub max_segment_duration_ms: u32,
+    /// audio kept before and after the speech of a segment
+    pub speech_pad_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        VadConfig {
+            threshold: 0.5,
+            neg_threshold: 0.1,
+            min_silence_duration_ms: 800,
+            min_speech_duration_ms: 300,
+            max_segment_duration_ms: 15000,
+            speech_pad_ms: 200,
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.neg_threshold > self.threshold {
+            return Err("vad neg_threshold must not be above threshold".to_string());
+        }
+        if ms_to_samples(self.max_segment_duration_ms) < WINDOW_SIZE_SAMPLES as u64 {
+            return Err(format!(
+                "vad max_segment_duration_ms must be at least one {}ms window",
+                samples_to_ms(WINDOW_SIZE_SAMPLES as u64)
+            ));
+        }
+        if self.min_silence_duration_ms == 0 {
+            return Err("vad min_silence_duration_ms must not be 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
 pub struct VadState {
     /// vad model required parameter
     model: OnnxModel,
     h: Tensor,
     c: Tensor,
 
-    /// vad detection state
+    /// vad detection state, positions are sample offsets from the stream start
     triggered: bool,
-    speech_start_ts: u32,
-    speech_end_ts: u32,
-    window_count: u32,
+    speech_start_ts: u64,
+    speech_end_ts: u64,
+    /// number of samples fed into vad
+    current_sample: u64,
+    /// position of the first sample in the ring buffer
+    buffer_start_ts: u64,
+    /// number of segments emitted so far
+    segment_count: usize,
+    /// samples which found no room in the ring buffer, not yet reported
+    lost_samples: u64,
+    config: VadConfig,
 
-    /// 15s audio data ring buffer
+    /// max segment duration audio data ring buffer
     rb_prod: F32RingBufProducer,
     rb_cons: F32RingBufConsumer,
 }
 
 impl VadState {
-    pub fn new() -> TractResult<VadState> {
+    pub fn new(config: VadConfig) -> TractResult<VadState> {
         let bytes = include_bytes!("../models/silero_vad.onnx");
         let mut cursor = Cursor::new(bytes);
         let model = onnx()
@@ -55,8 +114,10 @@ impl VadState {
             .into_optimized()?
             .into_runnable()?;
 
-        // 15s audio data ring buffer
-        let (prod, cons) = LocalRb::<f32, Vec<_>>::new(WINDOW_SIZE_SAMPLES * 500).split();
+        // max segment duration audio data ring buffer in whole windows
+        let windows =
+            (ms_to_samples(config.max_segment_duration_ms) as usize / WINDOW_SIZE_SAMPLES).max(1);
+        let (prod, cons) = LocalRb::<f32, Vec<_>>::new(WINDOW_SIZE_SAMPLES * windows).split();
 
         Ok(VadState {
             model,
@@ -65,23 +126,93 @@ impl VadState {
             triggered: false,
             speech_start_ts: 0,
             speech_end_ts: 0,
-            window_count: 0,
+            current_sample: 0,
+            buffer_start_ts: 0,
+            segment_count: 0,
+            lost_samples: 0,
+            config,
             rb_prod: prod,
             rb_cons: cons,
         })
     }
 
+    /// speech begins at the current sample, as if the model detected it
+    #[cfg(test)]
+    pub(crate) fn trigger(&mut self) {
+        self.triggered = true;
+        self.speech_start_ts = self.current_sample;
+    }
+
+    /// samples lost since the last call because the buffer had no room for them
+    pub fn take_lost_samples(&mut self) -> u64 {
+        std::mem::take(&mut self.lost_samples)
+    }
+
     fn init(&mut self) {
-        self.window_count = 0;
         self.speech_end_ts = 0;
-        self.speech_start_ts = 0;
+        self.speech_start_ts = self.current_sample;
+    }
+
+    /// drop buffered audio before `from` and move buffered audio until `to` into a segment
+    fn take_segment(&mut self, from: u64, to: u64) -> VadSegment {
+        self.segment_count += 1;
+        self.discard_until(from);
+
+        let len = to.saturating_sub(self.buffer_start_ts) as usize;
+        let data = self.rb_cons.pop_iter().take(len).collect_vec();
+        let start = self.buffer_start_ts;
+        let end = start + data.len() as u64;
+        self.buffer_start_ts = end;
+
+        VadSegment {
+            id: self.segment_count,
+            data,
+            start,
+            end,
+        }
+    }
+
+    fn discard_until(&mut self, position: u64) {
+        let len = position.saturating_sub(self.buffer_start_ts) as usize;
+        self.buffer_start_ts += self.rb_cons.skip(len) as u64;
+    }
+
+    /// segment from the padded speech start to the padded speech end
+    fn take_speech(&mut self, speech_end: u64) -> VadSegment {
+        let pad = ms_to_samples(self.config.speech_pad_ms);
+        let from = self.speech_start_ts.saturating_sub(pad);
+        let to = (speech_end + pad).min(self.current_sample);
+        self.take_segment(from, to)
     }
 }
 
 #[derive(Default)]
 pub struct VadSegment {
+    /// sequence number of this segment in the stream
+    pub id: usize,
     pub data: Vec<f32>,
-    pub duration: f32,
+    /// sample position of the first sample from the stream start
+    pub start: u64,
+    /// sample position after the last sample from the stream start
+    pub end: u64,
+}
+
+impl VadSegment {
+    pub fn start_ms(&self) -> i64 {
+        samples_to_ms(self.start)
+    }
+
+    pub fn end_ms(&self) -> i64 {
+        samples_to_ms(self.end)
+    }
+}
+
+fn samples_to_ms(samples: u64) -> i64 {
+    (samples * 1000 / SAMPLE_RATE as u64) as i64
+}
+
+fn ms_to_samples(ms: u32) -> u64 {
+    ms as u64 * SAMPLE_RATE as u64 / 1000
 }
 
 pub fn vad(
@@ -89,18 +220,12 @@ pub fn vad(
     audio_data: Vec<f32>,
     buf: &mut Vec<VadSegment>,
 ) -> TractResult<()> {
-    state.rb_prod.push_slice(&audio_data);
-
-    if state.rb_prod.is_full() {
-        state.init();
-        buf.push(VadSegment {
-            data: state.rb_cons.pop_iter().collect_vec(),
-            duration: 15.,
-        });
+    let speech_sample_offset = state.current_sample;
+    if push_samples(state, &audio_data, buf) {
         return Ok(());
     }
 
-    let pcm = Array::from_shape_vec((1, audio_data.len()), audio_data).unwrap();
+    let pcm = Array::from_shape_vec((1, audio_data.len()), audio_data)?;
     let pcm = pcm.into_arc_tensor();
     let samples = pcm.shape()[1];
     let chunk_len = samples.min(WINDOW_SIZE_SAMPLES);
@@ -116,35 +241,27 @@ pub fn vad(
 
     let speech_prob = outputs[0].as_slice::<f32>()?[1];
 
-    const MIN_SILENCE_DURATION_MS: u32 = 800;
-    const MIN_SPEECH_DURATION_MS: u32 = 300;
-    const THRESHOLD: f32 = 0.5;
-    const NEG_THRESHOLD: f32 = 0.1;
-    const MIN_SILENCE_SAMPLES: u32 = MIN_SILENCE_DURATION_MS * SAMPLE_RATE as u32 / 1000;
-    const MIN_SPEECH_SAMPLES: u32 = MIN_SPEECH_DURATION_MS * SAMPLE_RATE as u32 / 1000;
+    let threshold = state.config.threshold;
+    let neg_threshold = state.config.neg_threshold;
+    let min_silence_samples = ms_to_samples(state.config.min_silence_duration_ms);
+    let min_speech_samples = ms_to_samples(state.config.min_speech_duration_ms);
 
-    let speech_sample_offset = state.window_count * WINDOW_SIZE_SAMPLES as u32;
-    state.window_count += 1;
-
-    if speech_prob >= THRESHOLD && state.speech_end_ts != 0 {
+    if speech_prob >= threshold && state.speech_end_ts != 0 {
         state.speech_end_ts = 0;
     }
 
-    if speech_prob >= THRESHOLD && !state.triggered {
+    if speech_prob >= threshold && !state.triggered {
         state.triggered = true;
         state.speech_start_ts = speech_sample_offset;
-    } else if speech_prob < NEG_THRESHOLD && state.triggered {
+    } else if speech_prob < neg_threshold && state.triggered {
         if state.speech_end_ts == 0 {
             state.speech_end_ts = speech_sample_offset;
         }
 
-        if speech_sample_offset - state.speech_end_ts >= MIN_SILENCE_SAMPLES {
-            if state.speech_end_ts - state.speech_start_ts > MIN_SPEECH_SAMPLES {
+        if speech_sample_offset - state.speech_end_ts >= min_silence_samples {
+            if state.speech_end_ts - state.speech_start_ts > min_speech_samples {
+                buf.push(state.take_speech(state.speech_end_ts));
                 state.init();
-                buf.push(VadSegment {
-                    data: state.rb_cons.pop_iter().collect_vec(),
-                    duration: speech_sample_offset as f32 / SAMPLE_RATE,
-                });
             }
 
             state.triggered = false
@@ -154,6 +271,102 @@ pub fn vad(
     Ok(())
 }
 
+/// add samples to the ring buffer, a full buffer is split and takes the rest.
+/// the kept padding doesn't end on a window, so a window may not fit as a whole.
+/// returns whether the buffer was split
+fn push_samples(state: &mut VadState, mut data: &[f32], buf: &mut Vec<VadSegment>) -> bool {
+    let mut split = false;
+    loop {
+        let pushed = state.rb_prod.push_slice(data);
+        state.current_sample += pushed as u64;
+        data = &data[pushed..];
+
+        if !state.rb_prod.is_full() {
+            return split;
+        }
+
+        split_full_buffer(state, buf);
+        split = true;
+        if data.is_empty() {
+            return split;
+        }
+
+        if state.rb_prod.is_full() {
+            // the padding takes the whole buffer, the rest is lost
+            state.lost_samples += data.len() as u64;
+            skip(state, data.len() as u64, buf);
+            return split;
+        }
+    }
+}
+
+/// a full buffer ends the pending speech as a segment
+fn split_full_buffer(state: &mut VadState, buf: &mut Vec<VadSegment>) {
+    if state.triggered {
+        buf.push(state.take_speech(state.current_sample));
+    } else {
+        // nothing but silence and too short speech, keep the padding of the next speech
+        let pad = ms_to_samples(state.config.speech_pad_ms);
+        state.discard_until(state.current_sample.saturating_sub(pad));
+    }
+
+    state.init();
+}
+
+/// add the samples short of a window at the end of the stream without detecting speech in them,
+/// the model takes whole windows. they go into the speech which `flush` forces out
+pub fn push_tail(state: &mut VadState, audio_data: &[f32], buf: &mut Vec<VadSegment>) {
+    push_samples(state, audio_data, buf);
+}
+
+/// force out the speech still pending at the end of the stream,
+/// `vad` only ends a segment after enough silence or a full buffer
+pub fn flush(state: &mut VadState, buf: &mut Vec<VadSegment>) {
+    if !state.triggered {
+        return;
+    }
+
+    let min_speech_samples = ms_to_samples(state.config.min_speech_duration_ms);
+    if let Some(end) = trailing_speech_end(
+        state.speech_start_ts,
+        state.speech_end_ts,
+        state.current_sample,
+        min_speech_samples,
+    ) {
+        buf.push(state.take_speech(end));
+    }
+
+    state.triggered = false;
+    state.init();
+}
+
+/// move on by `samples` lost samples, the pending speech ends before the gap
+/// and the audio buffered before it is dropped
+pub fn skip(state: &mut VadState, samples: u64, buf: &mut Vec<VadSegment>) {
+    flush(state, buf);
+
+    state.discard_until(state.current_sample);
+    state.current_sample += samples;
+    state.buffer_start_ts = state.current_sample;
+    state.init();
+}
+
+/// end of the trailing speech, at the silence which already began or the stream end,
+/// none when it is too short
+fn trailing_speech_end(
+    speech_start: u64,
+    speech_end: u64,
+    current_sample: u64,
+    min_speech_samples: u64,
+) -> Option<u64> {
+    let end = if speech_end != 0 {
+        speech_end
+    } else {
+        current_sample
+    };
+    (end.saturating_sub(speech_start) > min_speech_samples).then_some(end)
+}
+
 pub fn split_audio_data_with_window_size(
     audio_data: Vec<f32>,
 ) -> (Option<Vec<f32>>, Option<Vec<f32>>) {
@@ -171,3 +384,197 @@ pub fn split_audio_data_with_window_size(
         (Some(left.to_vec()), Some(right.to_vec()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_vad_config() {
+        assert!(VadConfig::default().check().is_ok());
+
+        let config = VadConfig {
+            neg_threshold: 0.6,
+            ..VadConfig::default()
+        };
+        assert!(config.check().is_err());
+
+        let config = VadConfig {
+            max_segment_duration_ms: 20,
+            ..VadConfig::default()
+        };
+        assert!(config.check().is_err());
+
+        let config = VadConfig {
+            min_silence_duration_ms: 0,
+            ..VadConfig::default()
+        };
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_split_full_buffer() {
+        // 4800 samples buffer, 960 samples padding
+        let mut state = VadState::new(VadConfig {
+            max_segment_duration_ms: 300,
+            speech_pad_ms: 60,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut buf = vec![];
+
+        // silence fills the buffer, only the padding of the next speech is kept
+        vad(&mut state, vec![0.0; 4800], &mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(state.buffer_start_ts, 3840);
+
+        // speech fills the buffer, the segment starts at the padding before the speech
+        state.triggered = true;
+        vad(&mut state, vec![0.0; 3840], &mut buf).unwrap();
+        assert_eq!(buf.len(), 1);
+        assert_eq!((buf[0].id, buf[0].start, buf[0].end), (1, 3840, 8640));
+        assert_eq!(buf[0].data.len(), 4800);
+
+        // the next segment continues where the split one ended
+        vad(&mut state, vec![0.0; 4800], &mut buf).unwrap();
+        assert_eq!(buf.len(), 2);
+        assert_eq!((buf[1].id, buf[1].start, buf[1].end), (2, 8640, 13440));
+        assert!(state.triggered);
+    }
+
+    #[test]
+    fn test_split_full_buffer_with_default_padding() {
+        // 4800 samples buffer, 3200 samples padding
+        let mut state = VadState::new(VadConfig {
+            max_segment_duration_ms: 300,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut buf = vec![];
+
+        for _ in 0..10 {
+            vad(&mut state, vec![0.0; WINDOW_SIZE_SAMPLES], &mut buf).unwrap();
+        }
+        assert!(buf.is_empty());
+        assert_eq!(state.buffer_start_ts, 1600);
+
+        // the buffer fills in the middle of a window, the rest of the window is kept
+        state.triggered = true;
+        for _ in 0..4 {
+            vad(&mut state, vec![0.0; WINDOW_SIZE_SAMPLES], &mut buf).unwrap();
+        }
+        assert_eq!(buf.len(), 1);
+        assert_eq!((buf[0].start, buf[0].end), (1600, 6400));
+        assert_eq!(buf[0].data.len(), 4800);
+        assert_eq!(state.current_sample, 6720);
+        assert_eq!(state.buffer_start_ts, 6400);
+        assert_eq!(state.rb_cons.len(), 320);
+
+        // the timestamps of the next segment don't drift
+        for _ in 0..10 {
+            vad(&mut state, vec![0.0; WINDOW_SIZE_SAMPLES], &mut buf).unwrap();
+        }
+        assert_eq!(buf.len(), 2);
+        assert_eq!((buf[1].start, buf[1].end), (6400, 11200));
+        assert_eq!(buf[1].data.len(), 4800);
+        assert_eq!(state.take_lost_samples(), 0);
+    }
+
+    #[test]
+    fn test_lost_samples() {
+        // the padding is longer than the 4800 samples buffer
+        let mut state = VadState::new(VadConfig {
+            max_segment_duration_ms: 300,
+            speech_pad_ms: 400,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut buf = vec![];
+
+        push_tail(&mut state, &vec![0.0; 4800], &mut buf);
+        assert_eq!(state.take_lost_samples(), 0);
+
+        push_tail(&mut state, &vec![0.0; 480], &mut buf);
+        assert_eq!(state.take_lost_samples(), 480);
+        assert_eq!(state.take_lost_samples(), 0);
+        assert_eq!(state.current_sample, 5280);
+        assert_eq!(state.buffer_start_ts, 5280);
+    }
+
+    /// samples the model took for silence, without running it
+    fn feed(state: &mut VadState, len: usize) {
+        state.rb_prod.push_slice(&vec![0.0; len]);
+        state.current_sample += len as u64;
+    }
+
+    #[test]
+    fn test_flush() {
+        // 960 samples padding, 480 samples shortest speech
+        let mut state = VadState::new(VadConfig {
+            speech_pad_ms: 60,
+            min_speech_duration_ms: 30,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut buf = vec![];
+
+        feed(&mut state, 1920);
+        state.triggered = true;
+        state.speech_start_ts = 1920;
+        feed(&mut state, 1920);
+        // silence began but was too short to end the speech
+        state.speech_end_ts = 2880;
+
+        flush(&mut state, &mut buf);
+        assert_eq!(buf.len(), 1);
+        assert_eq!((buf[0].start, buf[0].end), (960, 3840));
+        assert_eq!(buf[0].data.len(), 2880);
+        assert!(!state.triggered);
+        assert_eq!((state.speech_start_ts, state.speech_end_ts), (3840, 0));
+
+        // nothing is pending anymore
+        flush(&mut state, &mut buf);
+        assert_eq!(buf.len(), 1);
+    }
+
+    #[test]
+    fn test_skip() {
+        // 960 samples padding, 480 samples shortest speech
+        let mut state = VadState::new(VadConfig {
+            speech_pad_ms: 60,
+            min_speech_duration_ms: 30,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut buf = vec![];
+
+        feed(&mut state, 1920);
+        state.trigger();
+        feed(&mut state, 1920);
+
+        // the speech ends at the gap
+        skip(&mut state, 8000, &mut buf);
+        assert_eq!(buf.len(), 1);
+        assert_eq!((buf[0].start, buf[0].end), (960, 3840));
+        assert!(!state.triggered);
+
+        // positions after the gap include the lost samples
+        assert_eq!(state.current_sample, 11840);
+        assert_eq!(state.buffer_start_ts, 11840);
+        assert!(state.rb_cons.is_empty());
+        state.trigger();
+        feed(&mut state, 1920);
+        flush(&mut state, &mut buf);
+        assert_eq!((buf[1].start, buf[1].end), (11840, 13760));
+    }
+
+    #[test]
+    fn test_trailing_speech_end() {
+        // speech until the end of the stream
+        assert_eq!(trailing_speech_end(1000, 0, 9000, 4800), Some(9000));
+        // silence began before the end but was too short to end the segment
+        assert_eq!(trailing_speech_end(1000, 7000, 9000, 4800), Some(7000));
+        // too short to transcribe
+        assert_eq!(trailing_speech_end(1000, 0, 4000, 4800), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    downloader::{DownloadError, DownloadErrorKind, DownloaderConfig},
+    source::StreamInfo,
+    util::Log,
+    Error,
+};
+
+/// how a scheduled live stream or premiere is waited for
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WaitConfig {
+    /// wait for upcoming streams to start instead of failing
+    pub enable: bool,
+    /// seconds between polls of the stream metadata
+    pub poll_interval_secs: u64,
+    /// give up after waiting this many seconds, forever by default
+    pub max_wait_secs: Option<u64>,
+    /// show the countdown on a terminal, off while the terminal ui owns it
+    #[serde(skip)]
+    pub countdown: bool,
+}
+
+impl Default for WaitConfig {
+    fn default() -> Self {
+        WaitConfig {
+            enable: false,
+            poll_interval_secs: 60,
+            max_wait_secs: None,
+            countdown: true,
+        }
+    }
+}
+
+/// poll the metadata of `url` until the stream is no longer upcoming,
+/// a countdown to the scheduled start is shown on a terminal
+pub fn wait_for_stream(
+    url: &str,
+    downloader: &DownloaderConfig,
+    config: &WaitConfig,
+    logger: &Log,
+) -> crate::Result<StreamInfo> {
+    let started = now();
+    let mut countdown = Countdown::new(config.countdown);
+
+    loop {
+        let scheduled = match StreamInfo::fetch(url, downloader) {
+            Ok(info) if !info.is_upcoming() => {
+                countdown.clear();
+                return Ok(info);
+            }
+            Ok(info) => info.release_timestamp,
+            Err(e) => match Error::from(e) {
+                // some streams refuse to print metadata before the start
+                Error::Download(e) if e.kind == DownloadErrorKind::NotStarted => None,
+                Error::Download(e) if e.is_fatal() => {
+                    countdown.clear();
+                    return Err(Error::Download(e));
+                }
+                e => {
+                    logger.error(format!("failed to check the stream: {}", e));
+                    None
+                }
+            },
+        };
+
+        let waited = now() - started;
+        if config.max_wait_secs.is_some_and(|max| waited >= max as i64) {
+            countdown.clear();
+            return Err(Error::Download(DownloadError::new(
+                DownloadErrorKind::NotStarted,
+                format!("gave up after waiting {}", format_duration(waited)),
+            )));
+        }
+
+        let next_poll = now() + poll_delay(scheduled, now(), config.poll_interval_secs);
+        logger.verbose(format!(
+            "stream is upcoming, checking again in {}s",
+            next_poll - now()
+        ));
+
+        while now() < next_poll {
+            countdown.show(scheduled, now());
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// seconds until the next poll, which happens right at the scheduled start
+/// when it's sooner than the regular interval
+fn poll_delay(scheduled: Option<i64>, now: i64, interval: u64) -> i64 {
+    let interval = interval.max(1) as i64;
+
+    match scheduled {
+        Some(start) if start > now => (start - now).clamp(1, interval),
+        _ => interval,
+    }
+}
+
+/// single status line rewritten every second on stderr
+struct Countdown {
+    enable: bool,
+    shown: bool,
+}
+
+impl Countdown {
+    fn new(enable: bool) -> Countdown {
+        Countdown {
+            enable: enable && io::stderr().is_terminal(),
+            shown: false,
+        }
+    }
+
+    fn show(&mut self, scheduled: Option<i64>, now: i64) {
+        if !self.enable {
+            return;
+        }
+
+        let status = match scheduled {
+            Some(start) if start > now => format!("starts in {}", format_duration(start - now)),
+            Some(start) => format!("late by {}", format_duration(now - start)),
+            None => "start time unknown".to_string(),
+        };
+
+        eprint!("\r\x1b[2K[wait] stream is upcoming, {}", status);
+        let _ = io::stderr().flush();
+        self.shown = true;
+    }
+
+    fn clear(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+            self.shown = false;
+        }
+    }
+}
+
+/// `HH:MM:SS` prefixed with days when it's longer than a day
+fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    let days = secs / 86400;
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60
+    );
+
+    if days > 0 {
+        format!("{}d {}", days, time)
+    } else {
+        time
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_delay() {
+        assert_eq!(poll_delay(None, 100, 60), 60);
+        assert_eq!(poll_delay(Some(130), 100, 60), 30);
+        assert_eq!(poll_delay(Some(1000), 100, 60), 60);
+        // overdue streams keep being polled at the interval
+        assert_eq!(poll_delay(Some(50), 100, 60), 60);
+        assert_eq!(poll_delay(Some(130), 100, 0), 1);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(59), "00:00:59");
+        assert_eq!(format_duration(3600 * 5 + 61), "05:01:01");
+        assert_eq!(format_duration(86400 * 2 + 3600), "2d 01:00:00");
+        assert_eq!(format_duration(-5), "00:00:00");
+    }
+}